@@ -0,0 +1,133 @@
+use blake2::{ Blake2b512, Digest };
+
+/// Generic substrate SS58 prefix, used by the dev chains carp launches
+const SS58_PREFIX: u8 = 42;
+
+/// Parses an SS58 address, a `0x` prefixed 32-byte account id, or a `0x` prefixed 20-byte
+/// Ethereum address into the substrate account id it maps to
+pub fn parse_account(address: &str) -> Result<[u8; 32], String> {
+    if let Some(hex) = address.strip_prefix("0x") {
+        let bytes = decode_hex(hex).ok_or_else(|| format!("{address} is not valid hex"))?;
+        return match bytes.len() {
+            20 => {
+                let mut eth_address = [0u8; 20];
+                eth_address.copy_from_slice(&bytes);
+                Ok(map_eth_address(&eth_address))
+            }
+            32 => {
+                let mut account = [0u8; 32];
+                account.copy_from_slice(&bytes);
+                Ok(account)
+            }
+            _ => Err(format!("{address} is neither a 20-byte H160 nor a 32-byte account id")),
+        };
+    }
+    ss58_decode(address)
+}
+
+/// Account id pallet-revive maps an Ethereum address to: the H160 followed by twelve `0xEE`
+pub fn map_eth_address(address: &[u8; 20]) -> [u8; 32] {
+    let mut account = [0xee; 32];
+    account[..20].copy_from_slice(address);
+    account
+}
+
+/// Whether `account` is the pallet-revive mapping of an Ethereum address, which has no sr25519 key
+pub fn is_eth_mapped(account: &[u8; 32]) -> bool {
+    account[20..].iter().all(|&byte| byte == 0xee)
+}
+
+fn ss58_checksum(data: &[u8]) -> [u8; 2] {
+    let mut hasher = Blake2b512::new();
+    hasher.update(b"SS58PRE");
+    hasher.update(data);
+    let hash = hasher.finalize();
+    [hash[0], hash[1]]
+}
+
+pub fn ss58_encode(account: &[u8; 32]) -> String {
+    let mut data = Vec::with_capacity(35);
+    data.push(SS58_PREFIX);
+    data.extend_from_slice(account);
+    let checksum = ss58_checksum(&data);
+    data.extend_from_slice(&checksum);
+    bs58::encode(data).into_string()
+}
+
+fn ss58_decode(address: &str) -> Result<[u8; 32], String> {
+    let data = bs58
+        ::decode(address)
+        .into_vec()
+        .map_err(|_| format!("{address} is not a valid SS58 address"))?;
+    // Single byte prefixes only, which covers every network id below 64
+    if data.len() != 35 || data[0] >= 64 {
+        return Err(format!("{address} is not a valid SS58 account address"));
+    }
+    if ss58_checksum(&data[..33]) != data[33..] {
+        return Err(format!("{address} has an invalid SS58 checksum"));
+    }
+    let mut account = [0u8; 32];
+    account.copy_from_slice(&data[1..33]);
+    Ok(account)
+}
+
+pub fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+pub fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALICE: &str = "5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY";
+    const ALICE_ID: &str = "d43593c715fdd31c61141abd04a99fd6822c8558854ccde39a5684e7a56da27d";
+
+    #[test]
+    fn decodes_and_encodes_ss58() {
+        let account = parse_account(ALICE).unwrap();
+        assert_eq!(encode_hex(&account), ALICE_ID);
+        assert_eq!(ss58_encode(&account), ALICE);
+    }
+
+    #[test]
+    fn parses_hex_account_id() {
+        let account = parse_account(&format!("0x{ALICE_ID}")).unwrap();
+        assert_eq!(ss58_encode(&account), ALICE);
+    }
+
+    #[test]
+    fn rejects_bad_checksum() {
+        // Last character changed
+        let err = parse_account("5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQZ").unwrap_err();
+        assert!(err.contains("checksum"), "{err}");
+    }
+
+    #[test]
+    fn rejects_bad_length() {
+        assert!(parse_account("5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKut").is_err());
+        assert!(parse_account("0xd43593c7").is_err());
+        assert!(parse_account("0xd43593c").is_err());
+        assert!(parse_account("not an address").is_err());
+    }
+
+    #[test]
+    fn maps_eth_address() {
+        let account = parse_account("0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266").unwrap();
+        assert_eq!(
+            encode_hex(&account),
+            "f39fd6e51aad88f6f4ce6ab8827279cfffb92266eeeeeeeeeeeeeeeeeeeeeeee"
+        );
+        assert!(is_eth_mapped(&account));
+        assert!(!is_eth_mapped(&parse_account(ALICE).unwrap()));
+    }
+}
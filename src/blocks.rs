@@ -0,0 +1,117 @@
+use std::{
+    sync::{ Arc, atomic::{ AtomicBool, Ordering } },
+    thread::{ self, JoinHandle },
+    time::Duration,
+};
+
+use serde_json::{ Value, json };
+
+use crate::{
+    config::{ BlockProduction, Config, NodeConfig },
+    error::{ CarpError, Result },
+    rpc::{ self, hex_to_u64 },
+};
+
+/// omni-node's dev mode always seals on a timer, this one doesn't fire in practice (~49 days)
+const ON_DEMAND_BLOCK_TIME: u64 = u32::MAX as u64;
+/// How often the pool is checked for transactions to seal in instant mode
+const INSTANT_SEAL_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// `--dev-block-time` for the node, blocks are otherwise authored through `engine_createBlock`
+pub fn dev_block_time(node: &NodeConfig) -> u64 {
+    match node.block_production {
+        BlockProduction::Interval => node.dev_block_time,
+        BlockProduction::Instant | BlockProduction::Manual => ON_DEMAND_BLOCK_TIME,
+    }
+}
+
+pub fn describe(node: &NodeConfig) -> String {
+    match node.block_production {
+        BlockProduction::Interval => format!("every {}ms", node.dev_block_time),
+        BlockProduction::Instant => "one per transaction".to_string(),
+        BlockProduction::Manual => "on `carp mine`".to_string(),
+    }
+}
+
+/// Asks the node's manual seal engine for a finalized block on top of the best one
+pub fn create_block(port: u16, create_empty: bool) -> std::io::Result<Value> {
+    rpc::call(port, "engine_createBlock", json!([create_empty, true, null]))
+}
+
+fn best_block(port: u16) -> Option<u64> {
+    rpc::call(port, "chain_getHeader", json!([]))
+        .ok()
+        .and_then(|header| hex_to_u64(&header["number"]))
+}
+
+/// Authors `count` blocks on the running network, empty ones included
+pub fn mine(config: &Config, count: u32) -> Result<()> {
+    let port = config.node_rpc_port();
+    for _ in 0..count {
+        let block = create_block(port, true).map_err(|err| {
+            CarpError::Mine(format!("node RPC on port {port} did not author a block: {err}"))
+        })?;
+        let hash = block["hash"].as_str().unwrap_or_default();
+        match best_block(port) {
+            Some(number) => println!("Mined block #{number} {hash}"),
+            None => println!("Mined block {hash}"),
+        }
+    }
+    Ok(())
+}
+
+/// Authors blocks from a background thread until dropped
+pub struct Sealer {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Sealer {
+    /// Seals a block as soon as the transaction pool holds one
+    pub fn instant(port: u16) -> std::io::Result<Self> {
+        Self::spawn(move |_| {
+            let pending = rpc::call(port, "author_pendingExtrinsics", json!([]));
+            let has_pending = pending.is_ok_and(|pending| {
+                pending.as_array().is_some_and(|txs| !txs.is_empty())
+            });
+            if has_pending {
+                // Fails harmlessly when the transactions were sealed meanwhile
+                let _ = create_block(port, false);
+            } else {
+                thread::sleep(INSTANT_SEAL_POLL_INTERVAL);
+            }
+        })
+    }
+
+    /// Seals a block every `interval`, empty or not
+    pub fn every(port: u16, interval: Duration) -> std::io::Result<Self> {
+        Self::spawn(move |stop| {
+            let _ = create_block(port, true);
+            let mut slept = Duration::ZERO;
+            while slept < interval && !stop.load(Ordering::SeqCst) {
+                thread::sleep(INSTANT_SEAL_POLL_INTERVAL);
+                slept += INSTANT_SEAL_POLL_INTERVAL;
+            }
+        })
+    }
+
+    fn spawn(mut step: impl FnMut(&AtomicBool) + Send + 'static) -> std::io::Result<Self> {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread = thread::Builder::new().spawn(move || {
+            while !thread_stop.load(Ordering::SeqCst) {
+                step(&thread_stop);
+            }
+        })?;
+        Ok(Sealer { stop, thread: Some(thread) })
+    }
+}
+
+impl Drop for Sealer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
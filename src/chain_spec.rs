@@ -0,0 +1,485 @@
+use std::{ ffi::OsString, fs, path::{ Path, PathBuf }, process::{ Command, Stdio } };
+
+use blake2::{ Blake2b256, Blake2b512, Digest };
+use serde_json::{ Value, json };
+
+use crate::{
+    account::{ decode_hex, encode_hex },
+    config::Config,
+    error::{ CarpError, Result },
+    genesis,
+    process::run_capturing_stderr,
+    rpc,
+};
+
+const PLAIN_SPEC: &str = "plain.json";
+const RAW_SPEC: &str = "raw.json";
+/// Cached next to the raw spec
+const GENESIS_HASH_FILE: &str = "genesis_hash";
+/// Written into the node's base path, the genesis hash its database was built from
+const GENESIS_MARKER: &str = "carp-genesis";
+
+const HASH_STEP: &str = "hash runtime";
+const LIST_PRESETS_STEP: &str = "chain-spec-builder list-presets";
+const DISPLAY_PRESET_STEP: &str = "chain-spec-builder display-preset";
+const PRESET_STEP: &str = "check preset";
+const GENESIS_HEAD_STEP: &str = "export-genesis-head";
+const CREATE_STEP: &str = "chain-spec-builder create";
+const RAW_STEP: &str = "chain-spec-builder convert-to-raw";
+const VALIDATE_STEP: &str = "validate chain spec";
+const GENESIS_STEP: &str = "apply genesis patch";
+const PROPERTIES_STEP: &str = "apply chain spec properties";
+
+/// Runs a one-shot step of `bin` to completion, returning the failure reason with the stderr tail
+fn run_step(bin: &str, command: &mut Command) -> Result<std::result::Result<(), String>> {
+    let (status, stderr_tail) = run_capturing_stderr(command, true).map_err(|err| {
+        CarpError::spawn(bin, err)
+    })?;
+    if status.success() {
+        return Ok(Ok(()));
+    }
+    let mut reason = format!("{bin} exited with {status}");
+    if !stderr_tail.is_empty() {
+        reason.push('\n');
+        reason.push_str(&stderr_tail);
+    }
+    Ok(Err(reason))
+}
+
+/// Runs a builder subcommand that prints JSON on stdout
+fn query_builder(config: &Config, step: &str, args: &[OsString]) -> Result<Value> {
+    let builder = &config.dependencies.chain_spec_builder;
+    let failed = |reason: String| CarpError::ChainSpec { step: step.to_string(), reason };
+    let output = Command::new(config.bin_path(builder))
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|err| CarpError::spawn(&builder.bin, err))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stderr = stderr.trim_end();
+        return Err(failed(format!("{} exited with {}\n{}", builder.bin, output.status, stderr)));
+    }
+    serde_json
+        ::from_slice(&output.stdout)
+        .map_err(|err| failed(format!("{} printed invalid JSON: {}", builder.bin, err)))
+}
+
+/// Names of the genesis presets the configured runtime ships
+pub fn list_presets(config: &Config) -> Result<Vec<String>> {
+    let args: [OsString; 3] = [
+        "list-presets".into(),
+        "--runtime".into(),
+        config.runtime_path().into(),
+    ];
+    let presets = query_builder(config, LIST_PRESETS_STEP, &args)?;
+    presets["presets"]
+        .as_array()
+        .and_then(|presets| {
+            presets
+                .iter()
+                .map(|preset| preset.as_str().map(String::from))
+                .collect()
+        })
+        .ok_or_else(|| CarpError::ChainSpec {
+            step: LIST_PRESETS_STEP.to_string(),
+            reason: format!("unexpected output {presets}"),
+        })
+}
+
+/// The genesis patch a preset applies on top of the runtime's defaults
+pub fn display_preset(config: &Config, preset: &str) -> Result<Value> {
+    let args: [OsString; 5] = [
+        "display-preset".into(),
+        "--runtime".into(),
+        config.runtime_path().into(),
+        "--preset-name".into(),
+        preset.into(),
+    ];
+    query_builder(config, DISPLAY_PRESET_STEP, &args)
+}
+
+fn check_preset(config: &Config) -> Result<()> {
+    let preset = &config.chain_spec.preset;
+    let presets = list_presets(config)?;
+    if presets.contains(preset) {
+        return Ok(());
+    }
+    Err(CarpError::ChainSpec {
+        step: PRESET_STEP.to_string(),
+        reason: format!(
+            "{} has no preset `{}`, available presets: {}",
+            config.runtime_path().display(),
+            preset,
+            presets.join(", ")
+        ),
+    })
+}
+
+/// Prints each preset with the pallets it configures, or a single preset in full
+pub fn print_presets(config: &Config, name: Option<&str>) -> Result<()> {
+    if let Some(name) = name {
+        let preset = display_preset(config, name)?;
+        println!("{}", serde_json::to_string_pretty(&preset).map_err(std::io::Error::other)?);
+        return Ok(());
+    }
+
+    for name in list_presets(config)? {
+        let selected = if name == config.chain_spec.preset { " (selected)" } else { "" };
+        println!("{name}{selected}");
+        if let Some(pallets) = display_preset(config, &name)?.as_object() {
+            for (pallet, value) in pallets {
+                println!("  {pallet}: {}", summarize(value));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn summarize(value: &Value) -> String {
+    match value {
+        Value::Object(fields) => fields.keys().cloned().collect::<Vec<_>>().join(", "),
+        Value::Array(items) => format!("{} entries", items.len()),
+        value => value.to_string(),
+    }
+}
+
+/// Hash of the runtime blob and every parameter that ends up in the spec
+fn cache_key(config: &Config) -> std::result::Result<String, String> {
+    let runtime_path = config.runtime_path();
+    let runtime = fs
+        ::read(&runtime_path)
+        .map_err(|err| format!("could not read {}: {}", runtime_path.display(), err))?;
+    let spec = &config.chain_spec;
+    let params = json!({
+        "builder": config.dependencies.chain_spec_builder.toolchain(),
+        "para_id": spec.para_id,
+        "relay_chain": spec.relay_chain,
+        "preset": spec.preset,
+        "name": spec.name,
+        "id": spec.id,
+        "token_symbol": spec.token_symbol,
+        "token_decimals": spec.token_decimals,
+        "genesis": config.genesis,
+    });
+
+    let mut hasher = Blake2b512::new();
+    hasher.update(&runtime);
+    hasher.update(params.to_string());
+    Ok(encode_hex(&hasher.finalize()[..16]))
+}
+
+/// Copies the raw spec into the network's data directory, reusing a cached one when neither the
+/// runtime nor the parameters changed. Returns the cache entry's raw spec.
+pub fn generate_chain_spec(config: &Config) -> Result<PathBuf> {
+    let key = cache_key(config).map_err(|reason| {
+        CarpError::ChainSpec { step: HASH_STEP.to_string(), reason }
+    })?;
+    let dir = config.chain_spec_cache_dir().join(key);
+    let plain_path = dir.join(PLAIN_SPEC);
+    let raw_path = dir.join(RAW_SPEC);
+
+    if raw_path.is_file() {
+        eprintln!("Using cached chain spec {}", raw_path.display());
+    } else {
+        eprintln!("Generating chain spec...");
+        check_preset(config)?;
+        fs::create_dir_all(&dir)?;
+        build_plain_spec(config, &plain_path)?;
+        build_raw_spec(config, &plain_path, &raw_path)?;
+    }
+
+    fs::create_dir_all(config.network_dir())?;
+    fs::copy(&raw_path, config.chain_spec_path())?;
+    if let Some(output) = &config.chain_spec.output {
+        fs::copy(&raw_path, config.resolve(output))?;
+    }
+    Ok(raw_path)
+}
+
+fn build_plain_spec(config: &Config, path: &Path) -> Result<()> {
+    let spec = &config.chain_spec;
+    let builder = &config.dependencies.chain_spec_builder;
+    let args: [OsString; 11] = [
+        "--chain-spec-path".into(),
+        path.into(),
+        "create".into(),
+        "--runtime".into(),
+        config.runtime_path().into(),
+        "--para-id".into(),
+        spec.para_id.to_string().into(),
+        "--relay-chain".into(),
+        (&spec.relay_chain).into(),
+        "named-preset".into(),
+        (&spec.preset).into(),
+    ];
+    let mut command = Command::new(config.bin_path(builder));
+    run_step(&builder.bin, command.args(args))?.map_err(|reason| {
+        CarpError::ChainSpec { step: CREATE_STEP.to_string(), reason }
+    })?;
+
+    let mut chain_spec = validate_chain_spec(config, path).map_err(|reason| {
+        CarpError::ChainSpec { step: VALIDATE_STEP.to_string(), reason }
+    })?;
+
+    if !config.genesis.is_empty() {
+        genesis::apply(&config.genesis, &mut chain_spec).map_err(|reason| {
+            CarpError::ChainSpec { step: GENESIS_STEP.to_string(), reason }
+        })?;
+    }
+    apply_properties(config, &mut chain_spec).map_err(|reason| {
+        CarpError::ChainSpec { step: PROPERTIES_STEP.to_string(), reason }
+    })?;
+    write_chain_spec(path, &chain_spec)
+}
+
+/// Builds the genesis storage once, so every node start from the cache is identical
+fn build_raw_spec(config: &Config, plain_path: &Path, raw_path: &Path) -> Result<()> {
+    let builder = &config.dependencies.chain_spec_builder;
+    // Written under a temporary name, an existing raw spec marks a complete cache entry
+    let partial_path = raw_path.with_extension("json.partial");
+    let args: [OsString; 4] = [
+        "--chain-spec-path".into(),
+        (&partial_path).into(),
+        "convert-to-raw".into(),
+        plain_path.into(),
+    ];
+    let mut command = Command::new(config.bin_path(builder));
+    run_step(&builder.bin, command.args(args))?.map_err(|reason| {
+        CarpError::ChainSpec { step: RAW_STEP.to_string(), reason }
+    })?;
+    fs::rename(&partial_path, raw_path)?;
+    Ok(())
+}
+
+/// Rewrites the chain name, id and token properties wallets display
+fn apply_properties(config: &Config, chain_spec: &mut Value) -> std::result::Result<(), String> {
+    let spec = &config.chain_spec;
+    let root = chain_spec.as_object_mut().ok_or_else(|| "chain spec is not an object".to_string())?;
+
+    if let Some(name) = &spec.name {
+        root.insert("name".to_string(), json!(name));
+    }
+    if let Some(id) = &spec.id {
+        root.insert("id".to_string(), json!(id));
+    }
+
+    let properties = root
+        .entry("properties")
+        .or_insert_with(|| json!({}))
+        .as_object_mut()
+        .ok_or_else(|| "chain spec `properties` is not an object".to_string())?;
+    if let Some(symbol) = &spec.token_symbol {
+        properties.insert("tokenSymbol".to_string(), json!(symbol));
+    }
+    if let Some(decimals) = spec.token_decimals {
+        properties.insert("tokenDecimals".to_string(), json!(decimals));
+    }
+    Ok(())
+}
+
+fn write_chain_spec(path: &Path, chain_spec: &Value) -> Result<()> {
+    let contents = serde_json::to_string_pretty(chain_spec).map_err(std::io::Error::other)?;
+    fs::write(path, contents)?;
+    Ok(())
+}
+
+/// Makes sure the freshly written spec is valid JSON for the configured parachain
+fn validate_chain_spec(config: &Config, path: &Path) -> std::result::Result<Value, String> {
+    let contents = fs
+        ::read_to_string(path)
+        .map_err(|err| format!("could not read {}: {}", path.display(), err))?;
+    let spec: Value = serde_json
+        ::from_str(&contents)
+        .map_err(|err| format!("{} is not valid JSON: {}", path.display(), err))?;
+
+    let para_id = spec.get("para_id").or_else(|| spec.get("paraId")).and_then(Value::as_u64);
+    match para_id {
+        Some(para_id) if para_id == u64::from(config.chain_spec.para_id) => Ok(spec),
+        Some(para_id) => {
+            Err(
+                format!(
+                    "{} has para_id {} but {} is configured",
+                    path.display(),
+                    para_id,
+                    config.chain_spec.para_id
+                )
+            )
+        }
+        None => Err(format!("{} has no para_id", path.display())),
+    }
+}
+
+/// Wipes the node database and eth-rpc's index of it
+pub fn purge_chain(config: &Config) -> Result<()> {
+    println!("Purging previous chain data...");
+    let node = &config.dependencies.omni_node;
+    let base_path = config.node_base_path();
+    let args: [OsString; 6] = [
+        "purge-chain".into(),
+        "--chain".into(),
+        config.chain_spec_path().into(),
+        "--base-path".into(),
+        (&base_path).into(),
+        "-y".into(),
+    ];
+    let mut command = Command::new(config.bin_path(node));
+    run_step(&node.bin, command.args(args))?.map_err(CarpError::Purge)?;
+    ignore_missing(fs::remove_file(base_path.join(GENESIS_MARKER)))?;
+    ignore_missing(fs::remove_dir_all(config.eth_rpc_base_path()))
+}
+
+fn ignore_missing(result: std::io::Result<()>) -> Result<()> {
+    match result {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Genesis hash of the chain `chain_spec` describes, cached next to it
+fn genesis_hash(config: &Config, chain_spec: &Path) -> Result<String> {
+    let cached = chain_spec.with_file_name(GENESIS_HASH_FILE);
+    if let Ok(hash) = fs::read_to_string(&cached) {
+        return Ok(hash);
+    }
+
+    // A scratch base path, so the genesis is built from the spec instead of read from a database
+    let scratch = std::env::temp_dir().join(format!("carp-genesis-{}", std::process::id()));
+    let node = &config.dependencies.omni_node;
+    let output = Command::new(config.bin_path(node))
+        .arg("export-genesis-head")
+        .arg("--chain")
+        .arg(chain_spec)
+        .arg("--base-path")
+        .arg(&scratch)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|err| CarpError::spawn(&node.bin, err));
+    let _ = fs::remove_dir_all(&scratch);
+    let output = output?;
+
+    let failed = |reason: String| CarpError::ChainSpec {
+        step: GENESIS_HEAD_STEP.to_string(),
+        reason,
+    };
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stderr = stderr.trim_end();
+        return Err(failed(format!("{} exited with {}\n{}", node.bin, output.status, stderr)));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let head = stdout.trim();
+    let header = head
+        .strip_prefix("0x")
+        .and_then(decode_hex)
+        .ok_or_else(|| failed(format!("unexpected genesis head `{head}`")))?;
+
+    let hash = format!("0x{}", encode_hex(&Blake2b256::digest(&header)));
+    fs::write(&cached, &hash)?;
+    Ok(hash)
+}
+
+/// Refuses to reuse a database built from a different genesis than `chain_spec`
+pub fn check_database(config: &Config, chain_spec: &Path) -> Result<()> {
+    let base_path = config.node_base_path();
+    let Ok(recorded) = fs::read_to_string(base_path.join(GENESIS_MARKER)) else {
+        if base_path.join("chains").is_dir() {
+            println!(
+                "Warning: the genesis of the database in {} is unknown, reusing it as is",
+                base_path.display()
+            );
+        }
+        return Ok(());
+    };
+
+    let expected = genesis_hash(config, chain_spec)?;
+    if recorded.trim() != expected {
+        return Err(CarpError::GenesisMismatch {
+            base_path,
+            found: recorded.trim().to_string(),
+            expected,
+        });
+    }
+    println!("Reusing chain state in {}", base_path.display());
+    Ok(())
+}
+
+/// Remembers which genesis the running node's database was built from
+pub fn record_genesis(config: &Config) -> Result<()> {
+    let hash = rpc::call(config.node_rpc_port(), "chain_getBlockHash", json!([0]))?;
+    let hash = hash
+        .as_str()
+        .ok_or_else(|| std::io::Error::other(format!("unexpected genesis hash {hash}")))?;
+    fs::write(config.node_base_path().join(GENESIS_MARKER), hash)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overrides_name_id_and_token_properties() {
+        let mut config = Config::default();
+        config.chain_spec.name = Some("Carp Dev".to_string());
+        config.chain_spec.id = Some("carp_dev".to_string());
+        config.chain_spec.token_symbol = Some("UNIT".to_string());
+        config.chain_spec.token_decimals = Some(12);
+
+        // chain-spec-builder leaves `properties` out unless the runtime sets them
+        let mut spec = json!({ "name": "Custom", "id": "custom", "para_id": 100 });
+        apply_properties(&config, &mut spec).unwrap();
+        assert_eq!(
+            spec,
+            json!({
+                "name": "Carp Dev",
+                "id": "carp_dev",
+                "para_id": 100,
+                "properties": { "tokenSymbol": "UNIT", "tokenDecimals": 12 },
+            })
+        );
+    }
+
+    #[test]
+    fn keeps_unset_properties() {
+        let mut config = Config::default();
+        config.chain_spec.token_symbol = Some("UNIT".to_string());
+
+        let mut spec = json!({
+            "name": "Custom",
+            "properties": { "tokenSymbol": "WND", "tokenDecimals": 12, "ss58Format": 42 },
+        });
+        apply_properties(&config, &mut spec).unwrap();
+        assert_eq!(spec["name"], "Custom");
+        assert_eq!(
+            spec["properties"],
+            json!({ "tokenSymbol": "UNIT", "tokenDecimals": 12, "ss58Format": 42 })
+        );
+
+        assert!(apply_properties(&config, &mut json!({ "properties": [] })).is_err());
+    }
+
+    #[test]
+    fn validates_para_id() {
+        let dir = std::env::temp_dir().join(format!("carp-spec-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let check = |name: &str, contents: &str| {
+            let path = dir.join(name);
+            fs::write(&path, contents).unwrap();
+            validate_chain_spec(&Config::default(), &path)
+        };
+
+        let matching = check("matching.json", r#"{ "para_id": 100 }"#);
+        let camel_case = check("camel_case.json", r#"{ "paraId": 100 }"#);
+        let wrong = check("wrong.json", r#"{ "para_id": 2000 }"#);
+        let missing = check("missing.json", r#"{ "name": "Custom" }"#);
+        let invalid = check("invalid.json", "{ para_id");
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(matching.unwrap()["para_id"], 100);
+        assert!(camel_case.is_ok());
+        assert!(wrong.unwrap_err().contains("has para_id 2000 but 100 is configured"));
+        assert!(missing.unwrap_err().contains("has no para_id"));
+        assert!(invalid.unwrap_err().contains("is not valid JSON"));
+    }
+}
@@ -0,0 +1,85 @@
+use std::path::PathBuf;
+
+use clap::{ Parser, Subcommand };
+
+#[derive(Parser)]
+#[command(name = "carp", version, about = "Spin up a local omni-node + eth-rpc network")]
+pub struct Cli {
+    /// Path to a carp.toml, by default it is searched for from the working directory upward
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Runtime to use by file name or spec_name from `runtime.dir`, overrides `runtime.name`,
+    /// `runtime.path` and `runtime.build`
+    #[arg(long, global = true)]
+    pub runtime: Option<String>,
+
+    #[command(subcommand)]
+    pub command: Commands,
+}
+
+#[derive(Subcommand)]
+pub enum Commands {
+    /// Install dependencies, generate the chain spec, purge old data and start the network
+    Up {
+        /// Purge the chain state even in persistent mode
+        #[arg(long)]
+        fresh: bool,
+        /// Keep the network running in the background, `carp down` stops it
+        #[arg(long)]
+        detach: bool,
+        /// Supervise the services of a network prepared by `carp up --detach`
+        #[arg(long, hide = true)]
+        supervise: bool,
+    },
+    /// Stop a network started by `carp up`, from any shell
+    Down,
+    /// Show whether the network services are running, the block height and the endpoints
+    Status,
+    /// Check for and install the required binaries
+    Install {
+        /// Reinstall every dependency, e.g. after changing a local workspace
+        #[arg(long)]
+        force: bool,
+        /// Maximum number of dependencies built concurrently, overrides `dependencies.jobs`
+        #[arg(short, long)]
+        jobs: Option<usize>,
+    },
+    /// Chain spec operations
+    Spec {
+        #[command(subcommand)]
+        command: SpecCommands,
+    },
+    /// List the runtimes found in the runtimes directory with their versions
+    Runtimes,
+    /// List the runtime's genesis presets and what they configure
+    Presets {
+        /// Print this preset's genesis patch in full
+        name: Option<String>,
+    },
+    /// Purge the node's chain data
+    Purge,
+    /// Replace the running network's runtime, signed with the sudo key
+    Upgrade {
+        /// Runtime blob, plain or compact compressed wasm
+        wasm: PathBuf,
+        /// Use `system.authorizeUpgrade` and `system.applyAuthorizedUpgrade` instead of `setCode`
+        #[arg(long)]
+        authorize: bool,
+        /// Secret URI of the sudo key, overrides upgrade.signer (default `//Alice`)
+        #[arg(long)]
+        signer: Option<String>,
+    },
+    /// Author blocks on the running network, e.g. with `block_production = "manual"`
+    Mine {
+        /// Number of blocks
+        #[arg(default_value_t = 1)]
+        blocks: u32,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum SpecCommands {
+    /// Generate the chain spec from the runtime
+    Generate,
+}
@@ -0,0 +1,575 @@
+use std::{ fs, path::{ Component, Path, PathBuf } };
+
+use serde::{ Deserialize, Deserializer, Serialize };
+
+use crate::{
+    dependency::{ CHAIN_SPEC_BUILDER, ETH_RPC_BIN, POLKADOT_OMNI_NODE_BIN },
+    error::{ CarpError, Result },
+};
+
+pub const CONFIG_FILE: &str = "carp.toml";
+
+pub const DEFAULT_NODE_RPC_PORT: u16 = 9944;
+pub const DEFAULT_NODE_P2P_PORT: u16 = 30333;
+pub const DEFAULT_NODE_PROMETHEUS_PORT: u16 = 9615;
+pub const DEFAULT_ETH_RPC_PORT: u16 = 8545;
+
+const POLKADOT_SDK_GIT: &str = "https://github.com/paritytech/polkadot-sdk.git";
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub network: NetworkConfig,
+    pub runtime: RuntimeConfig,
+    pub chain_spec: ChainSpecConfig,
+    pub genesis: GenesisConfig,
+    pub node: NodeConfig,
+    pub eth_rpc: EthRpcConfig,
+    pub logs: LogsConfig,
+    pub upgrade: UpgradeConfig,
+    pub dependencies: DependenciesConfig,
+    /// Directory the config was loaded from, relative paths are resolved against it
+    #[serde(skip)]
+    pub root: PathBuf,
+}
+
+/// Every network keeps its data in `<data_dir>/<name>`: the `node` and `eth-rpc` base paths and the
+/// `chain_spec.json` they are started from
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkConfig {
+    pub name: String,
+    pub data_dir: PathBuf,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RuntimeConfig {
+    /// Explicit runtime blob, takes precedence over `name`
+    pub path: Option<PathBuf>,
+    /// Searched for `*.wasm` and `*.compact.compressed.wasm` blobs
+    pub dir: PathBuf,
+    /// File name without suffix or spec_name of the runtime to pick from `dir`, required when it
+    /// contains more than one runtime
+    pub name: Option<String>,
+    /// Build the runtime from a local cargo package instead, takes precedence over the above
+    pub build: Option<RuntimeBuildConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuntimeBuildConfig {
+    /// Directory of the runtime's cargo package
+    pub path: PathBuf,
+    /// Package name, defaults to the package at `path`
+    pub package: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChainSpecConfig {
+    pub para_id: u32,
+    pub relay_chain: String,
+    pub preset: String,
+    /// Generated specs are cached in `<cache_dir>/<hash of runtime and parameters>`, defaults to
+    /// `~/.carp/chain-specs`
+    pub cache_dir: Option<PathBuf>,
+    /// Also copy the raw spec here, e.g. to share it with other tools
+    pub output: Option<PathBuf>,
+    /// Overrides for the top-level fields chain-spec-builder fills with `Custom`/`custom`. The id
+    /// also names the node's database directory.
+    pub name: Option<String>,
+    pub id: Option<String>,
+    pub token_symbol: Option<String>,
+    pub token_decimals: Option<u8>,
+}
+
+/// Applied as a patch on top of the chosen genesis preset
+#[derive(Deserialize, Serialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct GenesisConfig {
+    pub accounts: Vec<GenesisAccount>,
+    pub sudo: Option<String>,
+    pub invulnerables: Vec<String>,
+    pub dev_accounts: DevAccountsConfig,
+}
+
+/// Ethereum accounts derived from a mnemonic and funded at genesis
+#[derive(Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DevAccountsConfig {
+    pub mnemonic: String,
+    /// Number of accounts to derive, 0 disables them
+    pub count: u32,
+    #[serde(deserialize_with = "deserialize_balance")]
+    pub balance: u128,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct GenesisAccount {
+    /// SS58 address, 0x-prefixed 32-byte account id or 0x-prefixed H160 Ethereum address
+    pub address: String,
+    /// In the chain's smallest unit. TOML integers stop at i64, so large balances can be strings.
+    #[serde(deserialize_with = "deserialize_balance")]
+    pub balance: u128,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NodeConfig {
+    pub block_production: BlockProduction,
+    /// Milliseconds between blocks with `interval` block production
+    pub dev_block_time: u64,
+    pub args: Vec<String>,
+    /// Keep the chain state between runs, it is only purged by `carp purge` or `carp up --fresh`
+    pub persistent: bool,
+    /// Ports left out use the conventional one, or a free one when that is taken. Configured ports
+    /// must be free.
+    pub rpc_port: Option<u16>,
+    pub p2p_port: Option<u16>,
+    pub prometheus_port: Option<u16>,
+    /// Lines below this level are kept out of the console, the log file gets everything
+    pub log_level: LogLevel,
+    /// How long to wait for the RPC to answer and the first block to be authored
+    pub ready_timeout_secs: u64,
+    /// Grace period between SIGTERM and SIGKILL when the network is stopped
+    pub stop_timeout_secs: u64,
+    pub restart: RestartConfig,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EthRpcConfig {
+    pub args: Vec<String>,
+    pub rpc_port: Option<u16>,
+    pub log_level: LogLevel,
+    /// How long to wait for `eth_blockNumber` to answer
+    pub ready_timeout_secs: u64,
+    pub stop_timeout_secs: u64,
+    pub restart: RestartConfig,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum BlockProduction {
+    /// A block every `dev_block_time` milliseconds
+    Interval,
+    /// A block as soon as a transaction arrives
+    Instant,
+    /// Blocks are only authored by `carp mine`
+    Manual,
+}
+
+/// Service output is written to `<network dir>/logs/<service>.log`
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogsConfig {
+    /// A log file is rotated to `<service>.log.1` once it grows past this size, 0 never rotates
+    pub max_size_mb: u64,
+    /// Number of rotated files kept per service
+    pub max_files: u32,
+    /// Color the service prefixes, only applies when the console is a terminal
+    pub color: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UpgradeConfig {
+    /// Secret URI of the sr25519 key `carp upgrade` signs with, it must be the chain's sudo key
+    pub signer: String,
+}
+
+/// Ordered from least to most verbose
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, PartialOrd)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RestartConfig {
+    pub policy: RestartPolicy,
+    pub max_restarts: u32,
+    /// Delay before the first restart, doubled after every consecutive crash
+    pub backoff_ms: u64,
+    pub max_backoff_ms: u64,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    Never,
+    OnFailure,
+    Always,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DependenciesConfig {
+    /// What to do when an installed binary doesn't match its pin
+    pub version_policy: VersionPolicy,
+    /// Pinned binaries are installed into `<toolchains_dir>/<tag or rev>/bin`, defaults to
+    /// `~/.carp/toolchains`
+    pub toolchains_dir: Option<PathBuf>,
+    /// Pass `--offline` to every cargo invocation
+    pub offline: bool,
+    /// Maximum number of dependencies built concurrently
+    pub jobs: usize,
+    pub omni_node: DependencyConfig,
+    pub chain_spec_builder: DependencyConfig,
+    pub eth_rpc: DependencyConfig,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct DependencyConfig {
+    pub bin: String,
+    pub package: String,
+    pub git: Option<String>,
+    pub tag: Option<String>,
+    pub rev: Option<String>,
+    /// Expected `<bin> --version`, otherwise the commit behind `tag` or `rev` is compared
+    pub version: Option<String>,
+    /// Local cargo workspace (e.g. a polkadot-sdk checkout) to install the package from
+    pub path: Option<PathBuf>,
+    /// Prebuilt binary used in place, nothing is installed
+    pub binary: Option<PathBuf>,
+    /// Tarball or directory mirror containing a prebuilt binary
+    pub archive: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum VersionPolicy {
+    #[default]
+    Warn,
+    Refuse,
+    Reinstall,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        NetworkConfig { name: "local".to_string(), data_dir: PathBuf::from("./.carp") }
+    }
+}
+
+impl Default for RuntimeConfig {
+    fn default() -> Self {
+        RuntimeConfig { path: None, dir: PathBuf::from("./runtimes"), name: None, build: None }
+    }
+}
+
+impl Default for ChainSpecConfig {
+    fn default() -> Self {
+        ChainSpecConfig {
+            para_id: 100,
+            relay_chain: "paseo".to_string(),
+            preset: "development".to_string(),
+            cache_dir: None,
+            output: None,
+            name: None,
+            id: None,
+            token_symbol: None,
+            token_decimals: None,
+        }
+    }
+}
+
+impl Default for DevAccountsConfig {
+    fn default() -> Self {
+        DevAccountsConfig {
+            // Hardhat and Anvil's default mnemonic
+            mnemonic: "test test test test test test test test test test test junk".to_string(),
+            count: 10,
+            balance: 1_000_000_000_000_000_000,
+        }
+    }
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        NodeConfig {
+            block_production: BlockProduction::Interval,
+            dev_block_time: 6000,
+            args: vec![],
+            persistent: false,
+            rpc_port: None,
+            p2p_port: None,
+            prometheus_port: None,
+            log_level: LogLevel::Info,
+            ready_timeout_secs: 120,
+            // Leaves the database time to flush
+            stop_timeout_secs: 30,
+            restart: RestartConfig::default(),
+        }
+    }
+}
+
+impl Default for EthRpcConfig {
+    fn default() -> Self {
+        EthRpcConfig {
+            args: vec!["--rpc-cors=all".to_string(), "--log=debug".to_string()],
+            rpc_port: None,
+            log_level: LogLevel::Info,
+            ready_timeout_secs: 60,
+            stop_timeout_secs: 10,
+            restart: RestartConfig::default(),
+        }
+    }
+}
+
+impl Default for LogsConfig {
+    fn default() -> Self {
+        LogsConfig { max_size_mb: 10, max_files: 5, color: true }
+    }
+}
+
+impl Default for UpgradeConfig {
+    fn default() -> Self {
+        UpgradeConfig { signer: "//Alice".to_string() }
+    }
+}
+
+impl Default for RestartConfig {
+    fn default() -> Self {
+        RestartConfig {
+            policy: RestartPolicy::OnFailure,
+            max_restarts: 5,
+            backoff_ms: 1000,
+            max_backoff_ms: 30_000,
+        }
+    }
+}
+
+impl Default for DependenciesConfig {
+    fn default() -> Self {
+        DependenciesConfig {
+            version_policy: VersionPolicy::default(),
+            toolchains_dir: None,
+            offline: false,
+            jobs: 2,
+            omni_node: DependencyConfig::tag(
+                POLKADOT_OMNI_NODE_BIN,
+                POLKADOT_OMNI_NODE_BIN,
+                "polkadot-stable2412"
+            ),
+            chain_spec_builder: DependencyConfig::tag(
+                CHAIN_SPEC_BUILDER,
+                "staging-chain-spec-builder",
+                "polkadot-stable2412"
+            ),
+            eth_rpc: DependencyConfig {
+                bin: ETH_RPC_BIN.to_string(),
+                package: "pallet-revive-eth-rpc".to_string(),
+                git: Some(POLKADOT_SDK_GIT.to_string()),
+                tag: None,
+                rev: Some("d1d92ab76004ce349a97fc5d325eaf9a4a7101b7".to_string()),
+                version: None,
+                path: None,
+                binary: None,
+                archive: None,
+            },
+        }
+    }
+}
+
+impl DependencyConfig {
+    /// Name of the toolchain directory the pinned binary is installed into
+    pub fn toolchain(&self) -> String {
+        if let Some(local) = self.path.as_ref().or(self.archive.as_ref()) {
+            let name = local.file_stem().unwrap_or_default().to_string_lossy();
+            return format!("local-{}-{:08x}", name, fnv1a(local.as_os_str().as_encoded_bytes()));
+        }
+        self.tag
+            .as_deref()
+            .or(self.rev.as_deref())
+            .or(self.version.as_deref())
+            .unwrap_or("latest")
+            .to_string()
+    }
+
+    fn tag(bin: &str, package: &str, tag: &str) -> Self {
+        DependencyConfig {
+            bin: bin.to_string(),
+            package: package.to_string(),
+            git: Some(POLKADOT_SDK_GIT.to_string()),
+            tag: Some(tag.to_string()),
+            rev: None,
+            version: None,
+            path: None,
+            binary: None,
+            archive: None,
+        }
+    }
+}
+
+impl Config {
+    /// Loads `path` if given, otherwise the first `carp.toml` found from the working directory
+    /// upward. Falls back to the built-in defaults when there is none.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let path = match path {
+            Some(path) => Some(path.to_path_buf()),
+            None => Self::discover(&std::env::current_dir()?),
+        };
+
+        let Some(path) = path else {
+            return Ok(Config { root: std::env::current_dir()?, ..Default::default() });
+        };
+
+        let contents = fs
+            ::read_to_string(&path)
+            .map_err(|err| CarpError::Config(format!("{}: {}", path.display(), err)))?;
+        let mut config: Config = toml
+            ::from_str(&contents)
+            .map_err(|err| CarpError::Config(format!("{}: {}", path.display(), err)))?;
+        config
+            .check_network_name()
+            .map_err(|err| CarpError::Config(format!("{}: {}", path.display(), err)))?;
+        config.root = fs
+            ::canonicalize(&path)?
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        eprintln!("Using config {}", path.display());
+        Ok(config)
+    }
+
+    /// The name becomes a directory under `data_dir`, which purging deletes
+    fn check_network_name(&self) -> std::result::Result<(), String> {
+        let mut components = Path::new(&self.network.name).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(_)), None) => Ok(()),
+            _ => {
+                Err(format!("network.name `{}` must be a plain directory name", self.network.name))
+            }
+        }
+    }
+
+    fn discover(start: &Path) -> Option<PathBuf> {
+        start
+            .ancestors()
+            .map(|dir| dir.join(CONFIG_FILE))
+            .find(|candidate| candidate.is_file())
+    }
+
+    pub fn resolve(&self, path: &Path) -> PathBuf {
+        if path.is_absolute() { path.to_path_buf() } else { self.root.join(path) }
+    }
+
+    /// Like `resolve`, also expanding a leading `~`
+    pub fn resolve_user_path(&self, path: &Path) -> PathBuf {
+        self.resolve(&expand_home(path))
+    }
+
+    pub fn toolchains_dir(&self) -> PathBuf {
+        match &self.dependencies.toolchains_dir {
+            Some(dir) => self.resolve_user_path(dir),
+            None => home_dir().join(".carp").join("toolchains"),
+        }
+    }
+
+    /// `cargo install --root` directory for `dep`
+    pub fn install_root(&self, dep: &DependencyConfig) -> PathBuf {
+        self.toolchains_dir().join(dep.toolchain())
+    }
+
+    /// Absolute path of the pinned binary, used for every spawned process
+    pub fn bin_path(&self, dep: &DependencyConfig) -> PathBuf {
+        match &dep.binary {
+            Some(binary) => self.resolve_user_path(binary),
+            None => self.install_root(dep).join("bin").join(&dep.bin),
+        }
+    }
+
+    /// `<data_dir>/<name>`, holding everything the network writes
+    pub fn network_dir(&self) -> PathBuf {
+        self.resolve_user_path(&self.network.data_dir).join(&self.network.name)
+    }
+
+    pub fn node_base_path(&self) -> PathBuf {
+        self.network_dir().join("node")
+    }
+
+    pub fn eth_rpc_base_path(&self) -> PathBuf {
+        self.network_dir().join("eth-rpc")
+    }
+
+    pub fn logs_dir(&self) -> PathBuf {
+        self.network_dir().join("logs")
+    }
+
+    /// The raw spec every process of the network is started from
+    pub fn chain_spec_path(&self) -> PathBuf {
+        self.network_dir().join("chain_spec.json")
+    }
+
+    /// Ports as resolved by `ports::allocate`, or the conventional ones before that
+    pub fn node_rpc_port(&self) -> u16 {
+        self.node.rpc_port.unwrap_or(DEFAULT_NODE_RPC_PORT)
+    }
+
+    pub fn node_p2p_port(&self) -> u16 {
+        self.node.p2p_port.unwrap_or(DEFAULT_NODE_P2P_PORT)
+    }
+
+    pub fn node_prometheus_port(&self) -> u16 {
+        self.node.prometheus_port.unwrap_or(DEFAULT_NODE_PROMETHEUS_PORT)
+    }
+
+    pub fn eth_rpc_port(&self) -> u16 {
+        self.eth_rpc.rpc_port.unwrap_or(DEFAULT_ETH_RPC_PORT)
+    }
+
+    /// The runtime `runtime::select` picked, or the configured path before selection
+    pub fn runtime_path(&self) -> PathBuf {
+        self.resolve(self.runtime.path.as_deref().unwrap_or(&self.runtime.dir))
+    }
+
+    pub fn chain_spec_cache_dir(&self) -> PathBuf {
+        match &self.chain_spec.cache_dir {
+            Some(dir) => self.resolve_user_path(dir),
+            None => home_dir().join(".carp").join("chain-specs"),
+        }
+    }
+}
+
+pub fn home_dir() -> PathBuf {
+    std::env::var_os("HOME").map(PathBuf::from).unwrap_or_default()
+}
+
+fn expand_home(path: &Path) -> PathBuf {
+    match path.strip_prefix("~") {
+        Ok(rest) => home_dir().join(rest),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+/// Stable 32-bit FNV-1a, used to keep toolchain directory names short
+fn fnv1a(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c9dc5, |hash, byte| (hash ^ u32::from(*byte)).wrapping_mul(0x01000193))
+}
+
+fn deserialize_balance<'de, D>(deserializer: D) -> std::result::Result<u128, D::Error>
+    where D: Deserializer<'de>
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Balance {
+        Integer(u64),
+        String(String),
+    }
+
+    match Balance::deserialize(deserializer)? {
+        Balance::Integer(balance) => Ok(u128::from(balance)),
+        Balance::String(balance) => {
+            balance.replace('_', "").parse().map_err(serde::de::Error::custom)
+        }
+    }
+}
@@ -0,0 +1,414 @@
+use std::{
+    collections::VecDeque,
+    ffi::OsString,
+    fs,
+    path::{ Path, PathBuf },
+    process::{ Command, Stdio },
+    sync::{ Mutex, PoisonError },
+    thread,
+    time::{ Duration, Instant },
+};
+
+use serde_json::Value;
+
+use crate::{
+    config::{ Config, DependencyConfig, VersionPolicy },
+    error::{ CarpError, Result },
+    process::run_capturing_stderr,
+    version::{ self, VersionCheck },
+};
+
+pub const POLKADOT_OMNI_NODE_BIN: &str = "polkadot-omni-node";
+pub const CHAIN_SPEC_BUILDER: &str = "chain-spec-builder";
+pub const ETH_RPC_BIN: &str = "eth-rpc";
+
+#[derive(Clone)]
+pub enum GitInstallType {
+    Tag,
+    CommitHash,
+}
+
+#[derive(Clone)]
+pub struct GitOptions {
+    pub url: String,
+    pub tag_or_hash: String,
+    pub install_type: GitInstallType,
+}
+
+#[derive(Clone)]
+pub enum InstallSource {
+    CratesIo,
+    Git(GitOptions),
+    /// A local cargo workspace such as a polkadot-sdk checkout, built with `cargo install --path`
+    Workspace(PathBuf),
+    /// A prebuilt binary that is used in place
+    Binary(PathBuf),
+    /// A tarball, or a directory mirror, containing prebuilt binaries
+    Archive(PathBuf),
+}
+
+pub struct Dependency {
+    pub bin: String,
+    pub install_bin: String,
+    pub source: InstallSource,
+    /// Version `<bin> --version` must report, checked instead of the git pin when set
+    pub expected_version: Option<String>,
+    /// `cargo install --root` of the toolchain the binary belongs to
+    pub root: PathBuf,
+}
+
+impl Dependency {
+    pub fn new(bin: &str, install_bin: &str, source: InstallSource, root: &Path) -> Self {
+        Dependency {
+            bin: bin.to_string(),
+            install_bin: install_bin.to_string(),
+            source,
+            expected_version: None,
+            root: root.to_path_buf(),
+        }
+    }
+
+    pub fn path(&self) -> PathBuf {
+        match &self.source {
+            InstallSource::Binary(path) => path.clone(),
+            _ => self.root.join("bin").join(&self.bin),
+        }
+    }
+
+    pub fn git(&self) -> Option<&GitOptions> {
+        match &self.source {
+            InstallSource::Git(git) => Some(git),
+            _ => None,
+        }
+    }
+
+    pub fn from_config(config: &Config, dep: &DependencyConfig) -> Result<Self> {
+        let sources = [
+            dep.git.is_some(),
+            dep.path.is_some(),
+            dep.binary.is_some(),
+            dep.archive.is_some(),
+        ]
+            .into_iter()
+            .filter(|set| *set)
+            .count();
+        if sources > 1 {
+            return Err(
+                CarpError::Config(
+                    format!(
+                        "{}: only one of `git`, `path`, `binary` or `archive` may be set",
+                        dep.bin
+                    )
+                )
+            );
+        }
+
+        let source = if let Some(path) = &dep.path {
+            InstallSource::Workspace(config.resolve_user_path(path))
+        } else if let Some(binary) = &dep.binary {
+            InstallSource::Binary(config.resolve_user_path(binary))
+        } else if let Some(archive) = &dep.archive {
+            InstallSource::Archive(config.resolve_user_path(archive))
+        } else {
+            match (&dep.git, &dep.tag, &dep.rev) {
+                (None, None, None) => InstallSource::CratesIo,
+                (Some(url), Some(tag), None) => {
+                    InstallSource::Git(GitOptions::new(url, tag, GitInstallType::Tag))
+                }
+                (Some(url), None, Some(rev)) => {
+                    InstallSource::Git(GitOptions::new(url, rev, GitInstallType::CommitHash))
+                }
+                _ => {
+                    return Err(
+                        CarpError::Config(
+                            format!(
+                                "{}: a git dependency needs exactly one of `tag` or `rev`",
+                                dep.bin
+                            )
+                        )
+                    );
+                }
+            }
+        };
+
+        let mut dependency = Dependency::new(
+            &dep.bin,
+            &dep.package,
+            source,
+            &config.install_root(dep)
+        );
+        dependency.expected_version = dep.version.clone();
+        Ok(dependency)
+    }
+}
+
+impl GitOptions {
+    pub fn new(url: &str, tag: &str, install_type: GitInstallType) -> Self {
+        GitOptions { url: url.to_string(), tag_or_hash: tag.to_string(), install_type }
+    }
+}
+
+pub fn dependencies(config: &Config) -> Result<Vec<Dependency>> {
+    let deps = &config.dependencies;
+    [&deps.omni_node, &deps.chain_spec_builder, &deps.eth_rpc]
+        .into_iter()
+        .map(|dep| Dependency::from_config(config, dep))
+        .collect()
+}
+
+fn install_failed(dep: &Dependency, code: Option<i32>, stderr_tail: String) -> CarpError {
+    CarpError::InstallFailed { bin: dep.bin.clone(), code, stderr_tail }
+}
+
+fn run_install_step(dep: &Dependency, command: &mut Command) -> Result<()> {
+    let (status, stderr_tail) = run_capturing_stderr(command.stdout(Stdio::null()), false)?;
+    if !status.success() {
+        return Err(install_failed(dep, status.code(), stderr_tail));
+    }
+    Ok(())
+}
+
+/// Finds the directory of the package inside a cargo workspace without touching the network
+fn workspace_package_dir(dep: &Dependency, workspace: &Path) -> Result<PathBuf> {
+    let output = Command::new("cargo")
+        .args(["metadata", "--no-deps", "--offline", "--format-version", "1", "--manifest-path"])
+        .arg(workspace.join("Cargo.toml"))
+        .output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        return Err(install_failed(dep, output.status.code(), stderr));
+    }
+
+    let metadata: Value = serde_json
+        ::from_slice(&output.stdout)
+        .map_err(|err| install_failed(dep, None, err.to_string()))?;
+    metadata["packages"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find(|package| package["name"] == dep.install_bin.as_str())
+        .and_then(|package| {
+            Some(Path::new(package["manifest_path"].as_str()?).parent()?.to_path_buf())
+        })
+        .ok_or_else(|| {
+            install_failed(
+                dep,
+                None,
+                format!("package {} not found in {}", dep.install_bin, workspace.display())
+            )
+        })
+}
+
+fn find_file(dir: &Path, name: &str) -> Option<PathBuf> {
+    for entry in fs::read_dir(dir).ok()?.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            if let Some(found) = find_file(&path, name) {
+                return Some(found);
+            }
+        } else if entry.file_name() == name {
+            return Some(path);
+        }
+    }
+    None
+}
+
+/// Copies the binary out of a directory mirror, or a tarball unpacked into the toolchain
+fn install_from_archive(dep: &Dependency, archive: &Path) -> Result<()> {
+    fs::create_dir_all(dep.root.join("bin"))?;
+
+    let search_dir = if archive.is_dir() {
+        archive.to_path_buf()
+    } else {
+        let unpack_dir = dep.root.join("unpacked");
+        fs::create_dir_all(&unpack_dir)?;
+        run_install_step(
+            dep,
+            Command::new("tar").arg("-xf").arg(archive).arg("-C").arg(&unpack_dir)
+        )?;
+        unpack_dir
+    };
+
+    let found = find_file(&search_dir, &dep.bin).ok_or_else(|| {
+        install_failed(dep, None, format!("{} not found in {}", dep.bin, archive.display()))
+    })?;
+    fs::copy(found, dep.path())?;
+    Ok(())
+}
+
+fn install_dependency(dep: &Dependency, force: bool, offline: bool) -> Result<()> {
+    let mut command = Command::new("cargo");
+    command.arg("install").arg("--root").arg(&dep.root);
+    if force {
+        command.arg("--force");
+    }
+    if offline {
+        command.arg("--offline");
+    }
+
+    match &dep.source {
+        InstallSource::CratesIo => {
+            command.env("CARGO_TARGET_DIR", dep.root.join("target")).arg(&dep.install_bin);
+        }
+        InstallSource::Git(git) => {
+            let flag = match git.install_type {
+                GitInstallType::Tag => "--tag",
+                GitInstallType::CommitHash => "--rev",
+            };
+            // Dependencies pinned to the same release share a target dir, so common crates are
+            // only compiled once
+            command
+                .env("CARGO_TARGET_DIR", dep.root.join("target"))
+                .args(["--git", &git.url, flag, &git.tag_or_hash, &dep.install_bin]);
+        }
+        InstallSource::Workspace(workspace) => {
+            let package_dir = workspace_package_dir(dep, workspace)?;
+            command.args([OsString::from("--locked"), "--path".into(), package_dir.into()]);
+        }
+        InstallSource::Binary(path) => {
+            return Err(
+                CarpError::Config(
+                    format!("{}: prebuilt binary {} does not exist", dep.bin, path.display())
+                )
+            );
+        }
+        InstallSource::Archive(archive) => {
+            return install_from_archive(dep, archive);
+        }
+    }
+
+    run_install_step(dep, &mut command)
+}
+
+pub struct InstallOptions {
+    pub policy: VersionPolicy,
+    /// Reinstall every dependency, even when the installed version matches
+    pub force: bool,
+    pub offline: bool,
+    /// Maximum number of concurrent `cargo install`s
+    pub jobs: usize,
+}
+
+/// Prints one line per dependency whenever its install state changes
+struct Progress {
+    width: usize,
+    total: usize,
+}
+
+impl Progress {
+    fn report(&self, index: usize, dep: &Dependency, state: &str) {
+        let width = self.width;
+        println!("[{}/{}] {:<width$} {}", index + 1, self.total, dep.bin, state);
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 60 { format!("{}m{:02}s", secs / 60, secs % 60) } else { format!("{secs}s") }
+}
+
+/// Installs dependencies concurrently, up to `jobs` at a time. Dependencies sharing a toolchain
+/// are built one after the other since they share a cargo target dir.
+fn install_all(installs: Vec<(Dependency, bool)>, jobs: usize, offline: bool) -> Result<()> {
+    if installs.is_empty() {
+        return Ok(());
+    }
+
+    let progress = Progress {
+        width: installs
+            .iter()
+            .map(|(dep, _)| dep.bin.len())
+            .max()
+            .unwrap_or_default(),
+        total: installs.len(),
+    };
+
+    let mut groups: Vec<Vec<(usize, Dependency, bool)>> = Vec::new();
+    for (index, (dep, force)) in installs.into_iter().enumerate() {
+        progress.report(index, &dep, "queued");
+        match groups.iter_mut().find(|group| group[0].1.root == dep.root) {
+            Some(group) => group.push((index, dep, force)),
+            None => groups.push(vec![(index, dep, force)]),
+        }
+    }
+
+    let workers = jobs.clamp(1, groups.len());
+    let queue = Mutex::new(VecDeque::from(groups));
+    let errors = Mutex::new(Vec::new());
+
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| {
+                loop {
+                    let group = queue.lock().unwrap_or_else(PoisonError::into_inner).pop_front();
+                    let Some(group) = group else {
+                        return;
+                    };
+                    for (index, dep, force) in group {
+                        progress.report(index, &dep, "building");
+                        let started = Instant::now();
+                        let result = install_dependency(&dep, force, offline);
+                        let elapsed = format_duration(started.elapsed());
+                        match result {
+                            Ok(()) => progress.report(index, &dep, &format!("done in {elapsed}")),
+                            Err(err) => {
+                                progress.report(index, &dep, &format!("failed after {elapsed}"));
+                                errors.lock().unwrap_or_else(PoisonError::into_inner).push(err);
+                            }
+                        }
+                    }
+                }
+            });
+        }
+    });
+
+    match errors.into_inner().unwrap_or_else(PoisonError::into_inner).into_iter().next() {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+pub fn check_dependencies(dependencies: Vec<Dependency>, options: &InstallOptions) -> Result<()> {
+    let mut installs = Vec::new();
+
+    for dep in dependencies {
+        if options.force && !matches!(dep.source, InstallSource::Binary(_)) {
+            installs.push((dep, true));
+            continue;
+        }
+
+        let Some(installed) = version::probe(&dep.path())? else {
+            println!("{} is not installed", dep.bin);
+            installs.push((dep, false));
+            continue;
+        };
+
+        match version::check(&dep, &installed) {
+            VersionCheck::Match => println!("{} {} IS INSTALLED!", dep.bin, installed.version),
+            VersionCheck::Unknown(reason) => {
+                println!(
+                    "{} {} IS INSTALLED! (version not verified: {})",
+                    dep.bin,
+                    installed.version,
+                    reason
+                );
+            }
+            VersionCheck::Mismatch { found, expected } => {
+                match options.policy {
+                    VersionPolicy::Warn => {
+                        eprintln!("Warning: {} is {} but {} is pinned", dep.bin, found, expected);
+                    }
+                    VersionPolicy::Refuse => {
+                        return Err(CarpError::VersionMismatch { bin: dep.bin, found, expected });
+                    }
+                    VersionPolicy::Reinstall => {
+                        println!("{} is {} but {} is pinned", dep.bin, found, expected);
+                        installs.push((dep, true));
+                    }
+                }
+            }
+        }
+    }
+
+    install_all(installs, options.jobs, options.offline)
+}
@@ -0,0 +1,169 @@
+use std::{ fmt, path::PathBuf, process::ExitStatus };
+
+pub type Result<T> = std::result::Result<T, CarpError>;
+
+#[derive(Debug)]
+pub enum CarpError {
+    Io(std::io::Error),
+    Config(String),
+    Runtime(String),
+    MissingBinary {
+        bin: String,
+    },
+    InstallFailed {
+        bin: String,
+        code: Option<i32>,
+        stderr_tail: String,
+    },
+    VersionMismatch {
+        bin: String,
+        found: String,
+        expected: String,
+    },
+    ChainSpec {
+        step: String,
+        reason: String,
+    },
+    Purge(String),
+    Upgrade(String),
+    GenesisMismatch {
+        base_path: PathBuf,
+        found: String,
+        expected: String,
+    },
+    PortInUse {
+        name: String,
+        port: u16,
+    },
+    ServiceCrashed {
+        name: String,
+        status: Option<ExitStatus>,
+    },
+    NotReady {
+        name: String,
+        timeout_secs: u64,
+    },
+    ForceKilled {
+        names: Vec<String>,
+    },
+    AlreadyRunning {
+        pid: u32,
+    },
+    DetachFailed {
+        status: ExitStatus,
+        log: PathBuf,
+    },
+    Mine(String),
+}
+
+impl CarpError {
+    /// Distinct process exit code per failure so scripts can tell them apart, 2 is left to clap
+    /// for command line errors
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            CarpError::Io(_) => 1,
+            CarpError::Config(_) => 3,
+            CarpError::MissingBinary { .. } => 10,
+            CarpError::InstallFailed { .. } => 11,
+            CarpError::ChainSpec { .. } => 12,
+            CarpError::Purge(_) => 13,
+            CarpError::ServiceCrashed { .. } => 14,
+            CarpError::NotReady { .. } => 15,
+            CarpError::VersionMismatch { .. } => 16,
+            CarpError::Runtime(_) => 17,
+            CarpError::Upgrade(_) => 18,
+            CarpError::GenesisMismatch { .. } => 19,
+            CarpError::PortInUse { .. } => 20,
+            CarpError::ForceKilled { .. } => 21,
+            CarpError::AlreadyRunning { .. } => 22,
+            CarpError::DetachFailed { .. } => 23,
+            CarpError::Mine(_) => 24,
+        }
+    }
+
+    /// Maps a failed spawn of `bin` to `MissingBinary` when it isn't installed
+    pub fn spawn(bin: &str, err: std::io::Error) -> Self {
+        if err.kind() == std::io::ErrorKind::NotFound {
+            CarpError::MissingBinary { bin: bin.to_string() }
+        } else {
+            CarpError::Io(err)
+        }
+    }
+}
+
+impl fmt::Display for CarpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CarpError::Io(err) => write!(f, "{err}"),
+            CarpError::Config(msg) => write!(f, "invalid configuration: {msg}"),
+            CarpError::Runtime(msg) => write!(f, "runtime: {msg}"),
+            CarpError::MissingBinary { bin } => {
+                write!(f, "`{bin}` is not installed, run `carp install`")
+            }
+            CarpError::InstallFailed { bin, code, stderr_tail } => {
+                match code {
+                    Some(code) => write!(f, "installing {bin} failed with exit code {code}")?,
+                    None => write!(f, "installing {bin} was terminated by a signal")?,
+                }
+                if !stderr_tail.is_empty() {
+                    write!(f, "\n{stderr_tail}")?;
+                }
+                Ok(())
+            }
+            CarpError::VersionMismatch { bin, found, expected } => {
+                write!(f, "{bin} is {found} but {expected} is pinned")
+            }
+            CarpError::ChainSpec { step, reason } => {
+                write!(f, "chain spec generation failed at `{step}`: {reason}")
+            }
+            CarpError::Purge(msg) => write!(f, "purging chain data failed: {msg}"),
+            CarpError::Upgrade(msg) => write!(f, "runtime upgrade failed: {msg}"),
+            CarpError::GenesisMismatch { base_path, found, expected } => {
+                write!(
+                    f,
+                    "the database in {} has genesis {found} but the chain spec has genesis \
+                     {expected}, run `carp purge` or `carp up --fresh`",
+                    base_path.display()
+                )
+            }
+            CarpError::PortInUse { name, port } => {
+                write!(f, "{name} port {port} is already in use, pick another or leave it unset")
+            }
+            CarpError::ServiceCrashed { name, status: Some(status) } => {
+                write!(f, "{name} stayed down, last {status}")
+            }
+            CarpError::ServiceCrashed { name, status: None } => {
+                write!(f, "{name} stayed down, it could not be started")
+            }
+            CarpError::NotReady { name, timeout_secs } => {
+                write!(f, "{name} was not ready after {timeout_secs}s")
+            }
+            CarpError::ForceKilled { names } => {
+                let names = names.join(", ");
+                write!(f, "{names} did not stop within the grace period and was killed")
+            }
+            CarpError::AlreadyRunning { pid } => {
+                write!(f, "the network is already running under carp ({pid}), run `carp down`")
+            }
+            CarpError::DetachFailed { status, log } => {
+                write!(f, "the network exited during startup, {status}, see {}", log.display())
+            }
+            CarpError::Mine(msg) => write!(f, "mining failed: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for CarpError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CarpError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for CarpError {
+    fn from(err: std::io::Error) -> Self {
+        CarpError::Io(err)
+    }
+}
@@ -0,0 +1,91 @@
+use bip32::{ DerivationPath, XPrv };
+use bip39::Mnemonic;
+use sha3::{ Digest, Keccak256 };
+
+use crate::{ account::encode_hex, config::DevAccountsConfig };
+
+/// Ethereum account derived from the dev mnemonic
+pub struct EthAccount {
+    pub address: [u8; 20],
+    pub private_key: [u8; 32],
+}
+
+impl EthAccount {
+    /// EIP-55 checksummed address
+    pub fn address(&self) -> String {
+        let hex = encode_hex(&self.address);
+        let hash = Keccak256::digest(hex.as_bytes());
+        let checksummed: String = hex
+            .chars()
+            .enumerate()
+            .map(|(i, c)| {
+                let nibble = (hash[i / 2] >> (if i % 2 == 0 { 4 } else { 0 })) & 0x0f;
+                if nibble >= 8 { c.to_ascii_uppercase() } else { c }
+            })
+            .collect();
+        format!("0x{checksummed}")
+    }
+
+    pub fn private_key(&self) -> String {
+        format!("0x{}", encode_hex(&self.private_key))
+    }
+}
+
+/// Derives `m/44'/60'/0'/0/<index>` accounts like Hardhat and Anvil do, so the same mnemonic gives
+/// the same accounts in every tool
+pub fn derive(config: &DevAccountsConfig) -> Result<Vec<EthAccount>, String> {
+    let mnemonic = Mnemonic::parse(config.mnemonic.as_str()).map_err(|err| {
+        format!("invalid dev account mnemonic: {err}")
+    })?;
+    let seed = mnemonic.to_seed("");
+
+    (0..config.count)
+        .map(|index| {
+            let path: DerivationPath = format!("m/44'/60'/0'/0/{index}")
+                .parse()
+                .map_err(|err| format!("invalid derivation path: {err}"))?;
+            let key = XPrv::derive_from_path(&seed, &path).map_err(|err| {
+                format!("could not derive dev account {index}: {err}")
+            })?;
+
+            let public_key = key.public_key().public_key().to_sec1_point(false);
+            let hash = Keccak256::digest(&public_key.as_bytes()[1..]);
+            let mut address = [0u8; 20];
+            address.copy_from_slice(&hash[12..]);
+            Ok(EthAccount { address, private_key: key.to_bytes() })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derives_hardhat_accounts() {
+        let accounts = derive(&DevAccountsConfig { count: 2, ..Default::default() }).unwrap();
+        assert_eq!(accounts[0].address(), "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266");
+        assert_eq!(
+            accounts[0].private_key(),
+            "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80"
+        );
+        assert_eq!(accounts[1].address(), "0x70997970C51812dc3A010C7d01b50e0d17dc79C8");
+    }
+
+    #[test]
+    fn checksums_address() {
+        // EIP-55 test vector
+        let mut address = [0u8; 20];
+        address.copy_from_slice(
+            &crate::account::decode_hex("5aaeb6053f3e94c9b9a09f33669435e7ef1beaed").unwrap()
+        );
+        let account = EthAccount { address, private_key: [0; 32] };
+        assert_eq!(account.address(), "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed");
+    }
+
+    #[test]
+    fn rejects_invalid_mnemonic() {
+        let config = DevAccountsConfig { mnemonic: "test test".to_string(), ..Default::default() };
+        assert!(derive(&config).is_err());
+    }
+}
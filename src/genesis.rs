@@ -0,0 +1,173 @@
+use serde_json::{ Map, Number, Value, json };
+
+use crate::{
+    account::{ is_eth_mapped, map_eth_address, parse_account, ss58_encode },
+    config::GenesisConfig,
+    eth_accounts,
+};
+
+impl GenesisConfig {
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty() &&
+            self.sudo.is_none() &&
+            self.invulnerables.is_empty() &&
+            self.dev_accounts.count == 0
+    }
+}
+
+fn ss58(address: &str) -> Result<String, String> {
+    Ok(ss58_encode(&parse_account(address)?))
+}
+
+fn object<'a>(value: &'a mut Value, key: &str) -> Result<&'a mut Map<String, Value>, String> {
+    value
+        .as_object_mut()
+        .ok_or_else(|| "genesis patch is not an object".to_string())?
+        .entry(key)
+        .or_insert_with(|| json!({}))
+        .as_object_mut()
+        .ok_or_else(|| format!("genesis `{key}` is not an object"))
+}
+
+/// Merges the configured accounts, sudo key and invulnerables into
+/// `genesis.runtimeGenesis.patch` of a plain chain spec
+pub fn apply(genesis: &GenesisConfig, spec: &mut Value) -> Result<(), String> {
+    let patch = spec
+        .pointer_mut("/genesis/runtimeGenesis/patch")
+        .ok_or_else(|| "chain spec has no genesis.runtimeGenesis.patch".to_string())?;
+
+    let mut endowed = genesis.accounts
+        .iter()
+        .map(|account| Ok((ss58(&account.address)?, account.balance)))
+        .collect::<Result<Vec<_>, String>>()?;
+    for account in eth_accounts::derive(&genesis.dev_accounts)? {
+        let address = ss58_encode(&map_eth_address(&account.address));
+        endowed.push((address, genesis.dev_accounts.balance));
+    }
+
+    if !endowed.is_empty() {
+        let balances = object(patch, "balances")?
+            .entry("balances")
+            .or_insert_with(|| json!([]))
+            .as_array_mut()
+            .ok_or_else(|| "genesis `balances.balances` is not a list".to_string())?;
+
+        for (address, balance) in endowed {
+            // A configured account replaces the preset's endowment for the same address
+            balances.retain(|entry| entry[0] != address.as_str());
+            balances.push(json!([address, Value::Number(Number::from(balance))]));
+        }
+    }
+
+    if let Some(sudo) = &genesis.sudo {
+        object(patch, "sudo")?.insert("key".to_string(), Value::String(ss58(sudo)?));
+    }
+
+    if !genesis.invulnerables.is_empty() {
+        let invulnerables = genesis.invulnerables
+            .iter()
+            .map(|address| {
+                let account = parse_account(address)?;
+                if is_eth_mapped(&account) {
+                    return Err(
+                        format!(
+                            "invulnerable {address} is an Ethereum account, collators need an \
+                             sr25519 account for their aura key"
+                        )
+                    );
+                }
+                Ok(ss58_encode(&account))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        // Collators need session keys, the account's own sr25519 key doubles as its aura key
+        let keys: Vec<Value> = invulnerables
+            .iter()
+            .map(|account| json!([account, account, { "aura": account }]))
+            .collect();
+        object(patch, "collatorSelection")?
+            .insert("invulnerables".to_string(), json!(invulnerables));
+        object(patch, "session")?.insert("keys".to_string(), Value::Array(keys));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALICE: &str = "5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY";
+    const BOB: &str = "5FHneW46xGXgs5mUiveU4sbTyGBzmstUspZC92UhjJM694ty";
+
+    fn preset() -> Value {
+        json!({
+            "genesis": { "runtimeGenesis": { "patch": {
+                "balances": { "balances": [[ALICE, 1000], [BOB, 5]] },
+                "sudo": { "key": BOB },
+                "collatorSelection": { "invulnerables": [BOB] },
+                "session": { "keys": [[BOB, BOB, { "aura": BOB }]] },
+            } } }
+        })
+    }
+
+    fn genesis(toml: &str) -> GenesisConfig {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn configured_account_replaces_preset_endowment() {
+        // Alice as a hex account id, with a balance beyond u64
+        let genesis = genesis(
+            r#"
+            dev_accounts.count = 1
+            [[accounts]]
+            address = "0xd43593c715fdd31c61141abd04a99fd6822c8558854ccde39a5684e7a56da27d"
+            balance = "340_282_366_920_938_463_463_374_607_431_768_211_455"
+            "#
+        );
+        let mut spec = preset();
+        apply(&genesis, &mut spec).unwrap();
+
+        let dev_accounts = eth_accounts::derive(&genesis.dev_accounts).unwrap();
+        let dev_account = ss58(&dev_accounts[0].address()).unwrap();
+        let balances = &spec["genesis"]["runtimeGenesis"]["patch"]["balances"]["balances"];
+        assert_eq!(
+            serde_json::to_string(balances).unwrap(),
+            format!(
+                r#"[["{BOB}",5],["{ALICE}",340282366920938463463374607431768211455],["{}",{}]]"#,
+                dev_account,
+                genesis.dev_accounts.balance
+            )
+        );
+    }
+
+    #[test]
+    fn rewrites_sudo_and_invulnerables_with_their_session_keys() {
+        let mut spec = preset();
+        let genesis = genesis(
+            &format!("sudo = \"{ALICE}\"\ninvulnerables = [\"{ALICE}\"]\ndev_accounts.count = 0")
+        );
+        apply(&genesis, &mut spec).unwrap();
+
+        let patch = &spec["genesis"]["runtimeGenesis"]["patch"];
+        assert_eq!(patch["sudo"]["key"], ALICE);
+        assert_eq!(patch["collatorSelection"]["invulnerables"], json!([ALICE]));
+        assert_eq!(patch["session"]["keys"], json!([[ALICE, ALICE, { "aura": ALICE }]]));
+        // Balances are left to the preset without configured or dev accounts
+        assert_eq!(patch["balances"]["balances"], json!([[ALICE, 1000], [BOB, 5]]));
+    }
+
+    #[test]
+    fn rejects_ethereum_invulnerables() {
+        let genesis = genesis(
+            "invulnerables = [\"0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266\"]"
+        );
+        assert!(apply(&genesis, &mut preset()).unwrap_err().contains("Ethereum account"));
+    }
+
+    #[test]
+    fn requires_a_genesis_patch() {
+        let err = apply(&GenesisConfig::default(), &mut json!({ "genesis": {} })).unwrap_err();
+        assert!(err.contains("runtimeGenesis.patch"));
+    }
+}
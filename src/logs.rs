@@ -0,0 +1,197 @@
+use std::{
+    fs::{ self, File, OpenOptions },
+    io::{ self, BufRead, BufReader, IsTerminal, Read, Write },
+    path::PathBuf,
+    process::Child,
+    sync::{ Arc, Mutex },
+    thread,
+};
+
+use crate::config::{ Config, LogLevel, LogsConfig };
+
+const RESET: &str = "\x1b[0m";
+/// Cycled through in the order the services are listed
+const COLORS: [&str; 4] = ["\x1b[36m", "\x1b[35m", "\x1b[33m", "\x1b[32m"];
+
+/// Appends lines to `path`, moving it to `path.1`, `path.2`, ... once it grows too large
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    max_files: u32,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, config: &LogsConfig) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile {
+            path,
+            file,
+            size,
+            max_size: config.max_size_mb.saturating_mul(1024 * 1024),
+            max_files: config.max_files,
+        })
+    }
+
+    fn rotated(&self, index: u32) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{index}"));
+        path.into()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.max_files > 0 {
+            for index in (1..self.max_files).rev() {
+                let from = self.rotated(index);
+                if from.exists() {
+                    fs::rename(from, self.rotated(index + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+        }
+        self.file = File::create(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = (line.len() as u64) + 1;
+        if self.max_size > 0 && self.size > 0 && self.size + len > self.max_size {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{line}")?;
+        self.size += len;
+        Ok(())
+    }
+}
+
+/// Substrate style lines carry their level right after the timestamp
+fn line_level(line: &str) -> Option<LogLevel> {
+    line.split_whitespace()
+        .take(4)
+        .find_map(|word| {
+            match word {
+                "ERROR" => Some(LogLevel::Error),
+                "WARN" => Some(LogLevel::Warn),
+                "INFO" => Some(LogLevel::Info),
+                "DEBUG" => Some(LogLevel::Debug),
+                "TRACE" => Some(LogLevel::Trace),
+                _ => None,
+            }
+        })
+}
+
+/// Indented and empty lines continue the line above, like multi-line errors, and share its level.
+/// Other untagged lines, like panics or a final `Error: ...`, are always worth showing.
+fn continued_level(line: &str, previous: LogLevel) -> LogLevel {
+    if let Some(level) = line_level(line) {
+        level
+    } else if line.is_empty() || line.starts_with(char::is_whitespace) {
+        previous
+    } else {
+        LogLevel::Error
+    }
+}
+
+/// Output of one service, shared by every process the supervisor spawns for it
+pub struct ServiceLog {
+    prefix: String,
+    level: LogLevel,
+    file: Mutex<RotatingFile>,
+}
+
+impl ServiceLog {
+    /// Tees the child's stdout and stderr into the log file and the console until it exits
+    pub fn attach(self: &Arc<Self>, mut child: Child) -> io::Result<Child> {
+        if let Some(stdout) = child.stdout.take() {
+            self.forward(stdout)?;
+        }
+        if let Some(stderr) = child.stderr.take() {
+            self.forward(stderr)?;
+        }
+        Ok(child)
+    }
+
+    fn forward(self: &Arc<Self>, stream: impl Read + Send + 'static) -> io::Result<()> {
+        let log = self.clone();
+        thread::Builder::new().spawn(move || {
+            let mut level = LogLevel::Info;
+            for line in BufReader::new(stream).lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                level = continued_level(&line, level);
+                log.write(&line, level);
+            }
+        })?;
+        Ok(())
+    }
+
+    fn write(&self, line: &str, level: LogLevel) {
+        let mut file = self.file.lock().unwrap_or_else(|err| err.into_inner());
+        if let Err(err) = file.write_line(line) {
+            eprintln!("{}failed to write {}: {}", self.prefix, file.path.display(), err);
+        }
+        drop(file);
+        if level <= self.level {
+            println!("{}{}", self.prefix, line);
+        }
+    }
+}
+
+/// Opens `<logs dir>/<name>.log` for every service up front so their console prefixes line up
+pub fn open(config: &Config, services: &[(&str, LogLevel)]) -> io::Result<Vec<Arc<ServiceLog>>> {
+    let dir = config.logs_dir();
+    fs::create_dir_all(&dir)?;
+
+    let color =
+        config.logs.color && std::env::var_os("NO_COLOR").is_none() && io::stdout().is_terminal();
+    let width = services
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or(0);
+
+    services
+        .iter()
+        .enumerate()
+        .map(|(index, (name, level))| {
+            let prefix = if color {
+                format!("{}{name:>width$}{RESET} | ", COLORS[index % COLORS.len()])
+            } else {
+                format!("{name:>width$} | ")
+            };
+            let file = RotatingFile::open(dir.join(format!("{name}.log")), &config.logs)?;
+            Ok(Arc::new(ServiceLog { prefix, level: *level, file: Mutex::new(file) }))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_substrate_line_levels() {
+        let line = "2025-01-01 12:00:00.000  WARN tokio-runtime-worker sync: Peer misbehaved";
+        assert_eq!(line_level(line), Some(LogLevel::Warn));
+        assert_eq!(line_level("2025-01-01 12:00:00 DEBUG eth-rpc: polling"), Some(LogLevel::Debug));
+        assert_eq!(line_level("2025-01-01 12:00:00 ERROR rpc: failed"), Some(LogLevel::Error));
+        // Only the first words are the header, a level further in is part of the message
+        assert_eq!(line_level("a b c d INFO"), None);
+        assert_eq!(line_level("Error: connection refused"), None);
+    }
+
+    #[test]
+    fn untagged_lines_are_shown_unless_continued() {
+        let debug = LogLevel::Debug;
+        assert_eq!(continued_level("    at src/main.rs:10", debug), LogLevel::Debug);
+        assert_eq!(continued_level("", debug), LogLevel::Debug);
+        assert_eq!(continued_level("Error: connection refused", debug), LogLevel::Error);
+        let panic = "thread 'main' panicked at src/main.rs:3:5:";
+        assert_eq!(continued_level(panic, debug), LogLevel::Error);
+        assert_eq!(continued_level("2025-01-01 12:00:00  INFO ready", debug), LogLevel::Info);
+    }
+}
@@ -1,178 +1,133 @@
-// use clap::Parser;
-use std::{ ffi::OsStr, process::{ Child, Command, ExitStatus, Stdio }, time::Duration };
-
-const POLKADOT_OMNI_NODE_BIN: &str = "polkadot-omni-node";
-const CHAIN_SPEC_BUILDER: &str = "chain-spec-builder";
-const ETH_RPC_BIN: &str = "eth-rpc";
-
-#[derive(Clone)]
-enum GitInstallType {
-    Tag,
-    CommitHash,
-}
-
-#[derive(Clone)]
-struct GitOptions {
-    url: String,
-    tag_or_hash: String,
-    install_type: GitInstallType,
-}
-
-struct Dependency {
-    bin: String,
-    install_bin: String,
-    git: Option<GitOptions>,
-}
-
-impl Dependency {
-    fn new(bin: &str, install_bin: &str, git: Option<GitOptions>) -> Self {
-        Dependency { bin: bin.to_string(), install_bin: install_bin.to_string(), git }
+use std::process::ExitCode;
+
+use clap::Parser;
+
+mod account;
+mod blocks;
+mod chain_spec;
+mod cli;
+mod config;
+mod dependency;
+mod error;
+mod eth_accounts;
+mod genesis;
+mod logs;
+mod network;
+mod ports;
+mod process;
+mod readiness;
+mod rpc;
+mod runtime;
+mod state;
+mod supervisor;
+mod upgrade;
+mod version;
+
+use cli::{ Cli, Commands, SpecCommands };
+use config::Config;
+use dependency::InstallOptions;
+use error::Result;
+
+fn install_options(config: &Config, force: bool, jobs: Option<usize>) -> InstallOptions {
+    InstallOptions {
+        policy: config.dependencies.version_policy,
+        force,
+        offline: config.dependencies.offline,
+        jobs: jobs.unwrap_or(config.dependencies.jobs),
     }
 }
 
-impl GitOptions {
-    fn new(url: &str, tag: &str, install_type: GitInstallType) -> Self {
-        GitOptions { url: url.to_string(), tag_or_hash: tag.to_string(), install_type }
+fn run(cli: Cli) -> Result<()> {
+    let mut config = Config::load(cli.config.as_deref())?;
+    if let Some(name) = cli.runtime {
+        config.runtime.build = None;
+        config.runtime.path = None;
+        config.runtime.name = Some(name);
+    }
+    let needs_runtime = matches!(
+        cli.command,
+        Commands::Up { .. } | Commands::Spec { .. } | Commands::Presets { .. } | Commands::Purge
+    );
+    if needs_runtime {
+        runtime::select(&mut config)?;
+    }
+    // Talk to the network on the ports it was actually started with
+    if
+        matches!(
+            cli.command,
+            Commands::Status | Commands::Upgrade { .. } | Commands::Mine { .. }
+        ) &&
+        let Some(state) = state::read(&config)?
+    {
+        state.apply_ports(&mut config);
     }
-}
-
-fn generate_child_process<I, S>(bin_name: S, args: I) -> Result<Child, std::io::Error>
-    where I: IntoIterator<Item = S>, S: AsRef<OsStr>
-{
-    Command::new(bin_name).args(args).spawn()
-}
-
-fn kill_process(id: u32) -> Result<ExitStatus, std::io::Error> {
-    generate_child_process("kill", ["-s", "TERM", &id.to_string()])?.wait()
-}
 
-fn install_dependency(dep: Dependency) -> Result<(), std::io::Error> {
-    let git: Option<GitOptions> = dep.git;
-    if let Some(git) = git {
-        match git.install_type {
-            GitInstallType::Tag => {
-                let _ = generate_child_process("cargo", [
-                    "install",
-                    "--git",
-                    &git.url,
-                    "--tag",
-                    &git.tag_or_hash,
-                    &dep.install_bin,
-                ])?.wait();
-                return Ok(());
+    match cli.command {
+        Commands::Up { supervise: true, .. } => {
+            // Everything else was prepared by the `carp up --detach` that started us
+            ports::allocate(&mut config)?;
+            network::up(&config, true)
+        }
+        Commands::Up { fresh, detach, .. } => {
+            network::ensure_not_running(&config)?;
+            // Make sure everything is installed
+            println!("Checking dependencies");
+            dependency::check_dependencies(
+                dependency::dependencies(&config)?,
+                &install_options(&config, false, None)
+            )?;
+            //Generate chain-spec from params
+            let chain_spec = chain_spec::generate_chain_spec(&config)?;
+            // Purge chain data, unless it is kept between runs
+            if config.node.persistent && !fresh {
+                chain_spec::check_database(&config, &chain_spec)?;
+            } else {
+                chain_spec::purge_chain(&config)?;
             }
-            GitInstallType::CommitHash => {
-                let _ = generate_child_process("cargo", [
-                    "install",
-                    "--git",
-                    &git.url,
-                    "--rev",
-                    &git.tag_or_hash,
-                    &dep.install_bin,
-                ])?.wait();
-                return Ok(());
+            if detach {
+                return network::detach(&mut config);
             }
+            ports::allocate(&mut config)?;
+            network::up(&config, false)
         }
-    } else {
-        let _ = generate_child_process("cargo", ["install", &dep.install_bin])?.wait();
+        Commands::Down => network::down(&config),
+        Commands::Status => network::status(&config),
+        Commands::Install { force, jobs } => {
+            println!("Checking dependencies");
+            dependency::check_dependencies(
+                dependency::dependencies(&config)?,
+                &install_options(&config, force, jobs)
+            )
+        }
+        Commands::Spec { command: SpecCommands::Generate } => {
+            chain_spec::generate_chain_spec(&config)?;
+            println!("{}", config.chain_spec_path().display());
+            Ok(())
+        }
+        Commands::Runtimes => runtime::print_runtimes(&config),
+        Commands::Presets { name } => chain_spec::print_presets(&config, name.as_deref()),
+        // The node finds its database through the id in the spec
+        Commands::Purge => {
+            network::ensure_not_running(&config)?;
+            chain_spec::generate_chain_spec(&config)?;
+            chain_spec::purge_chain(&config)
+        }
+        Commands::Upgrade { wasm, authorize, signer } => {
+            if let Some(signer) = signer {
+                config.upgrade.signer = signer;
+            }
+            upgrade::upgrade(&config, &wasm, authorize)
+        }
+        Commands::Mine { blocks } => blocks::mine(&config, blocks),
     }
-    Ok(())
 }
 
-fn check_dependencies(dependencies: Vec<Dependency>) -> Result<(), std::io::Error> {
-    dependencies.into_iter().for_each(|dep| {
-        if Command::new(&dep.bin).stdout(Stdio::null()).stderr(Stdio::null()).spawn().is_err() {
-            install_dependency(dep).expect("Could not install dependency");
-        } else {
-            println!("{} IS INSTALLED!", dep.bin);
+fn main() -> ExitCode {
+    match run(Cli::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {err}");
+            ExitCode::from(err.exit_code())
         }
-    });
-    Ok(())
-}
-
-fn main() -> Result<(), std::io::Error> {
-    let git_options = GitOptions::new(
-        "https://github.com/paritytech/polkadot-sdk.git",
-        "polkadot-stable2412",
-        GitInstallType::Tag
-    );
-
-    // Make sure everything is installed
-    println!("Checking dependencies");
-    let dependencies = vec![
-        Dependency::new(POLKADOT_OMNI_NODE_BIN, POLKADOT_OMNI_NODE_BIN, Some(git_options.clone())),
-        Dependency::new(
-            CHAIN_SPEC_BUILDER,
-            "staging-chain-spec-builder",
-            Some(git_options.clone())
-        ),
-        Dependency::new(
-            ETH_RPC_BIN,
-            "pallet-revive-eth-rpc",
-            Some(
-                GitOptions::new(
-                    "https://github.com/paritytech/polkadot-sdk.git",
-                    "d1d92ab76004ce349a97fc5d325eaf9a4a7101b7",
-                    GitInstallType::CommitHash
-                )
-            )
-        )
-    ];
-
-    check_dependencies(dependencies)?;
-    //Generate chain-spec from params
-    println!("Generating chain spec...");
-    let _chain_spec = generate_child_process(CHAIN_SPEC_BUILDER, [
-        "create",
-        "--runtime",
-        "./runtimes/westend.wasm",
-        "--para-id",
-        "100",
-        "--relay-chain",
-        "paseo",
-        "named-preset",
-        "development",
-    ])?.wait()?;
-
-    // Purge chain data
-    println!("Purging previous chain data...");
-    let _purge = generate_child_process(POLKADOT_OMNI_NODE_BIN, [
-        "purge-chain",
-        "--chain",
-        "./chain_spec.json",
-        "-y",
-    ])?.wait()?;
-
-    // Start the omninode
-    let omni_node = generate_child_process(POLKADOT_OMNI_NODE_BIN, [
-        "--chain",
-        "./chain_spec.json",
-        "--dev-block-time",
-        "6000",
-    ])?;
-
-    // Start the ETH RPC
-    let eth_rpc = generate_child_process(ETH_RPC_BIN, [
-        "--chain",
-        "./chain_spec.json",
-        "--rpc-cors=all",
-        "--log=debug",
-    ])?;
-
-    println!("🐋 🐋 🐋 🐋 🐋 🐋 🐋 🐋 🐋 🐋 🐋 🐋");
-    println!("🤖🤖🤖 OMNINODE IS STARTING 🤖🤖🤖");
-    println!("🐋 🐋 🐋 🐋 🐋 🐋 🐋 🐋 🐋 🐋 🐋 🐋");
-
-    ctrlc
-        ::set_handler(move || {
-            kill_process(omni_node.id()).expect("Omninode failed to be killed");
-            kill_process(eth_rpc.id()).expect("ETH RPC failed to be killed");
-            println!("Carp finished 🐋");
-            std::process::exit(0);
-        })
-        .expect("Error setting Ctrl-C handler");
-
-    loop {
-        std::thread::sleep(Duration::from_secs(1));
     }
 }
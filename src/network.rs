@@ -0,0 +1,426 @@
+use std::{
+    ffi::OsString,
+    fs,
+    process::{ Child, Command, Stdio },
+    sync::{ Arc, atomic::{ AtomicBool, Ordering } },
+    time::{ Duration, Instant },
+};
+
+use serde_json::json;
+
+use crate::{
+    blocks::{ self, Sealer },
+    chain_spec,
+    config::{ BlockProduction, Config, LogLevel },
+    error::{ CarpError, Result },
+    eth_accounts,
+    logs,
+    process::{
+        self,
+        force_kill_process,
+        generate_piped_child_process,
+        is_same_process,
+        kill_process,
+        terminate_process,
+    },
+    readiness,
+    rpc::{ self, hex_to_u64 },
+    state::{ self, ServiceState, State },
+    supervisor::{ Service, Supervisor },
+};
+
+/// Output of a carp started by `carp up --detach`, in the network directory
+const DETACHED_LOG: &str = "carp.log";
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// Time a carp asked to shut down gets on top of its services' grace periods
+const SUPERVISOR_EXIT_MARGIN: Duration = Duration::from_secs(5);
+
+pub fn start_node(config: &Config) -> std::io::Result<Child> {
+    let mut args: Vec<OsString> = vec![
+        "--chain".into(),
+        config.chain_spec_path().into(),
+        "--base-path".into(),
+        config.node_base_path().into(),
+        "--rpc-port".into(),
+        config.node_rpc_port().to_string().into(),
+        "--port".into(),
+        config.node_p2p_port().to_string().into(),
+        "--prometheus-port".into(),
+        config.node_prometheus_port().to_string().into(),
+        "--dev-block-time".into(),
+        blocks::dev_block_time(&config.node).to_string().into()
+    ];
+    args.extend(config.node.args.iter().map(OsString::from));
+    generate_piped_child_process(config.bin_path(&config.dependencies.omni_node), args)
+}
+
+pub fn start_eth_rpc(config: &Config) -> std::io::Result<Child> {
+    let mut args: Vec<OsString> = vec![
+        "--chain".into(),
+        config.chain_spec_path().into(),
+        "--base-path".into(),
+        config.eth_rpc_base_path().into(),
+        "--rpc-port".into(),
+        config.eth_rpc_port().to_string().into(),
+        "--node-rpc-url".into(),
+        format!("ws://127.0.0.1:{}", config.node_rpc_port()).into()
+    ];
+    args.extend(config.eth_rpc.args.iter().map(OsString::from));
+    generate_piped_child_process(config.bin_path(&config.dependencies.eth_rpc), args)
+}
+
+/// Records the services and ports for `carp status`, `carp down` and `carp upgrade`
+fn record(config: &Config, supervisor: &Supervisor, detached: bool, ready: bool) -> Result<()> {
+    state::write(config, &State::new(config, detached, ready, &supervisor.pids()))
+}
+
+/// Keeps supervising until `ready` returns true. Fails when the timeout passes, `name` or a
+/// critical service stays down, or the user interrupts the startup.
+fn wait_until_ready<F>(
+    config: &Config,
+    supervisor: &mut Supervisor,
+    shutdown: &AtomicBool,
+    detached: bool,
+    name: &str,
+    timeout: Duration,
+    ready: F
+) -> Result<bool>
+    where F: Fn() -> bool
+{
+    println!("Waiting for {name} to become ready...");
+    let deadline = Instant::now() + timeout;
+    loop {
+        if shutdown.load(Ordering::SeqCst) {
+            return Ok(false);
+        }
+        if supervisor.poll()? {
+            record(config, supervisor, detached, false)?;
+        }
+        supervisor.ensure_up(name)?;
+        if ready() {
+            println!("{name} is ready");
+            return Ok(true);
+        }
+        if Instant::now() >= deadline {
+            return Err(CarpError::NotReady {
+                name: name.to_string(),
+                timeout_secs: timeout.as_secs(),
+            });
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn supervise<'a>(
+    config: &'a Config,
+    supervisor: &mut Supervisor<'a>,
+    shutdown: &AtomicBool,
+    detached: bool
+) -> Result<()> {
+    let node_name = config.dependencies.omni_node.bin.as_str();
+    let eth_rpc_name = config.dependencies.eth_rpc.bin.as_str();
+    // A detached carp's console is carp.log, which is never rotated, so service output only goes
+    // to the service log files
+    let console_level = |level| if detached { LogLevel::Off } else { level };
+    let logs = logs::open(config, &[
+        (node_name, console_level(config.node.log_level)),
+        (eth_rpc_name, console_level(config.eth_rpc.log_level)),
+    ])?;
+    let (node_log, eth_rpc_log) = (logs[0].clone(), logs[1].clone());
+
+    // Start the omninode
+    supervisor.start(
+        Service::new(node_name, config.node.restart.clone(), move || {
+            node_log.attach(start_node(config)?)
+        })
+            .stop_timeout(Duration::from_secs(config.node.stop_timeout_secs))
+            .critical()
+    )?;
+    record(config, supervisor, detached, false)?;
+
+    println!("🐋 🐋 🐋 🐋 🐋 🐋 🐋 🐋 🐋 🐋 🐋 🐋");
+    println!("🤖🤖🤖 OMNINODE IS STARTING 🤖🤖🤖");
+    println!("🐋 🐋 🐋 🐋 🐋 🐋 🐋 🐋 🐋 🐋 🐋 🐋");
+
+    // eth-rpc needs the node RPC up and producing blocks
+    let authors_on_request = config.node.block_production != BlockProduction::Interval;
+    let node_timeout = Duration::from_secs(config.node.ready_timeout_secs);
+    if
+        !wait_until_ready(config, supervisor, shutdown, detached, node_name, node_timeout, || {
+            readiness::node_ready(config.node_rpc_port(), authors_on_request)
+        })?
+    {
+        return Ok(());
+    }
+    chain_spec::record_genesis(config)?;
+    let _instant_seal = match config.node.block_production {
+        BlockProduction::Instant => Some(Sealer::instant(config.node_rpc_port())?),
+        BlockProduction::Interval | BlockProduction::Manual => None,
+    };
+
+    // Start the ETH RPC
+    supervisor.start(
+        Service::new(eth_rpc_name, config.eth_rpc.restart.clone(), move || {
+            eth_rpc_log.attach(start_eth_rpc(config)?)
+        })
+            .stop_timeout(Duration::from_secs(config.eth_rpc.stop_timeout_secs))
+            .depends_on(node_name)
+    )?;
+    record(config, supervisor, detached, false)?;
+
+    let eth_rpc_timeout = Duration::from_secs(config.eth_rpc.ready_timeout_secs);
+    if
+        !wait_until_ready(
+            config,
+            supervisor,
+            shutdown,
+            detached,
+            eth_rpc_name,
+            eth_rpc_timeout,
+            || readiness::eth_rpc_ready(config.eth_rpc_port())
+        )?
+    {
+        return Ok(());
+    }
+
+    record(config, supervisor, detached, true)?;
+    println!("🐋 Network is ready");
+    print_endpoints(config);
+    print_dev_accounts(config)?;
+
+    while !shutdown.load(Ordering::SeqCst) {
+        if supervisor.poll()? {
+            record(config, supervisor, detached, true)?;
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+    Ok(())
+}
+
+fn print_endpoints(config: &Config) {
+    println!("  Node RPC:   ws://127.0.0.1:{}", config.node_rpc_port());
+    println!("  ETH RPC:    http://127.0.0.1:{}", config.eth_rpc_port());
+    println!("  Prometheus: http://127.0.0.1:{}/metrics", config.node_prometheus_port());
+    println!("  P2P port:   {}", config.node_p2p_port());
+    println!("  Blocks:     {}", blocks::describe(&config.node));
+    println!("  Logs:       {}", config.logs_dir().display());
+}
+
+fn print_dev_accounts(config: &Config) -> Result<()> {
+    let dev_accounts = &config.genesis.dev_accounts;
+    let accounts = eth_accounts::derive(dev_accounts).map_err(CarpError::Config)?;
+    if accounts.is_empty() {
+        return Ok(());
+    }
+
+    println!();
+    println!("Ethereum dev accounts ({} each, in the chain's smallest unit)", dev_accounts.balance);
+    for (index, account) in accounts.iter().enumerate() {
+        println!("  ({index}) {}", account.address());
+        println!("      private key: {}", account.private_key());
+    }
+    println!("  Mnemonic: {}", dev_accounts.mnemonic);
+    println!();
+    Ok(())
+}
+
+/// Runs the network until Ctrl-C or SIGTERM, `detached` when started by `carp up --detach`
+pub fn up(config: &Config, detached: bool) -> Result<()> {
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let handler_shutdown = shutdown.clone();
+    ctrlc
+        ::set_handler(move || handler_shutdown.store(true, Ordering::SeqCst))
+        .map_err(|err| CarpError::Io(std::io::Error::other(err)))?;
+
+    let mut supervisor = Supervisor::default();
+    let result = supervise(config, &mut supervisor, &shutdown, detached);
+
+    let killed = supervisor.stop_all();
+    let _ = state::remove(config);
+    println!("Carp finished 🐋");
+    result?;
+    if !killed.is_empty() {
+        return Err(CarpError::ForceKilled { names: killed });
+    }
+    Ok(())
+}
+
+/// Starts the network under a carp running in the background and returns once it is ready. The
+/// background carp runs this command again, with `--supervise` in place of `--detach`.
+pub fn detach(config: &mut Config) -> Result<()> {
+    let log_path = config.network_dir().join(DETACHED_LOG);
+    fs::create_dir_all(config.network_dir())?;
+    let log = fs::File::create(&log_path)?;
+
+    let mut command = Command::new(std::env::current_exe()?);
+    command
+        .args(std::env::args_os().skip(1).filter(|arg| arg != "--detach"))
+        .arg("--supervise")
+        .stdin(Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log);
+    let mut child = process::detach(&mut command).spawn()?;
+    println!(
+        "Starting the network in the background, carp ({}) logs to {}",
+        child.id(),
+        log_path.display()
+    );
+
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Err(CarpError::DetachFailed { status, log: log_path });
+        }
+        if let Some(state) = state::read(config)? && state.pid == child.id() && state.ready {
+            state.apply_ports(config);
+            break;
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+
+    println!("🐋 Network is ready");
+    print_endpoints(config);
+    print_dev_accounts(config)?;
+    println!("Run `carp down` to stop it");
+    Ok(())
+}
+
+fn stop_timeout(config: &Config, name: &str) -> Duration {
+    let secs = if name == config.dependencies.eth_rpc.bin {
+        config.eth_rpc.stop_timeout_secs
+    } else {
+        config.node.stop_timeout_secs
+    };
+    Duration::from_secs(secs)
+}
+
+fn wait_for_exit(pid: u32, start_time: Option<u64>, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    while is_same_process(pid, start_time) {
+        if Instant::now() >= deadline {
+            return false;
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+    true
+}
+
+/// Sends SIGTERM to a process started by another carp, SIGKILL when it outlives its grace period.
+/// Returns whether it had to be force-killed.
+fn stop_process(service: &ServiceState, timeout: Duration) -> Result<bool> {
+    let (name, pid) = (&service.name, service.pid);
+    println!("Stopping {name} ({pid})");
+    kill_process(pid)?;
+    if wait_for_exit(pid, service.start_time, timeout) {
+        return Ok(false);
+    }
+    println!("{name} did not exit within {}s, killing it", timeout.as_secs());
+    force_kill_process(pid)?;
+    Ok(true)
+}
+
+/// Stops the recorded services that are still alive, eth-rpc before the node it talks to, and
+/// returns the ones that had to be force-killed
+fn stop_services(config: &Config, state: &State) -> Result<Vec<String>> {
+    let mut killed = Vec::new();
+    for service in state.services.iter().rev() {
+        if
+            service.is_running() &&
+            stop_process(service, stop_timeout(config, &service.name))?
+        {
+            killed.push(service.name.clone());
+        }
+    }
+    Ok(killed)
+}
+
+/// Refuses to start over a running network, and cleans up after one whose carp crashed
+pub fn ensure_not_running(config: &Config) -> Result<()> {
+    let Some(state) = state::read(config)? else {
+        return Ok(());
+    };
+    if state.is_running() {
+        return Err(CarpError::AlreadyRunning { pid: state.pid });
+    }
+    println!("Cleaning up after carp ({}), it did not shut down cleanly", state.pid);
+    stop_services(config, &state)?;
+    state::remove(config)
+}
+
+/// Asks the carp running the network to shut it down, and stops whatever a crashed one left
+pub fn down(config: &Config) -> Result<()> {
+    let Some(state) = state::read(config)? else {
+        println!("No running network found");
+        return Ok(());
+    };
+
+    if state.is_running() {
+        // It stops its services in order and removes the state itself
+        println!("Stopping carp ({})", state.pid);
+        terminate_process(state.pid)?;
+        let timeout =
+            Duration::from_secs(config.node.stop_timeout_secs + config.eth_rpc.stop_timeout_secs) +
+            SUPERVISOR_EXIT_MARGIN;
+        if !wait_for_exit(state.pid, state.start_time, timeout) {
+            println!("carp ({}) did not exit, stopping its services directly", state.pid);
+        }
+    } else {
+        println!("carp ({}) is no longer running, stopping what it left behind", state.pid);
+    }
+
+    let killed = stop_services(config, &state)?;
+    state::remove(config)?;
+    println!("Carp finished 🐋");
+    if !killed.is_empty() {
+        return Err(CarpError::ForceKilled { names: killed });
+    }
+    Ok(())
+}
+
+/// `config` carries the ports recorded by the running network
+pub fn status(config: &Config) -> Result<()> {
+    let Some(state) = state::read(config)? else {
+        println!("No running network found");
+        return Ok(());
+    };
+
+    if state.is_running() {
+        let mode = if state.detached { "in the background" } else { "in the foreground" };
+        println!(
+            "Network {} is supervised by carp ({}) {mode}",
+            state.network_dir.display(),
+            state.pid
+        );
+    } else {
+        println!("carp ({}) is no longer running, it did not shut down cleanly", state.pid);
+        if !state.services.iter().any(ServiceState::is_running) {
+            state::remove(config)?;
+            println!("Removed its stale state");
+            return Ok(());
+        }
+        println!("Some of its services are still running, `carp down` stops them");
+    }
+
+    for service in &state.services {
+        let running = if service.is_running() { "running" } else { "stopped" };
+        println!("{:<20} {:<8} {running}", service.name, service.pid);
+    }
+    if !state.ready {
+        println!("The network is still starting");
+    }
+
+    let best_block = rpc
+        ::call(config.node_rpc_port(), "chain_getHeader", json!([]))
+        .ok()
+        .and_then(|header| hex_to_u64(&header["number"]));
+    let eth_block = rpc
+        ::call(config.eth_rpc_port(), "eth_blockNumber", json!([]))
+        .ok()
+        .and_then(|number| hex_to_u64(&number));
+    let height = |number: Option<u64>| {
+        number.map_or_else(|| "unreachable".to_string(), |number| format!("#{number}"))
+    };
+    println!("  Best block: {}", height(best_block));
+    println!("  ETH block:  {}", height(eth_block));
+    print_endpoints(config);
+    Ok(())
+}
@@ -0,0 +1,71 @@
+use std::net::{ Ipv4Addr, TcpListener };
+
+use crate::{
+    config::{
+        Config,
+        DEFAULT_ETH_RPC_PORT,
+        DEFAULT_NODE_P2P_PORT,
+        DEFAULT_NODE_PROMETHEUS_PORT,
+        DEFAULT_NODE_RPC_PORT,
+    },
+    error::{ CarpError, Result },
+};
+
+/// Binding every interface also catches the node's p2p listener on 0.0.0.0
+fn is_free(port: u16) -> bool {
+    TcpListener::bind((Ipv4Addr::UNSPECIFIED, port)).is_ok()
+}
+
+/// A port the OS considers free that isn't handed out yet
+fn any_free(allocated: &[u16]) -> Result<u16> {
+    loop {
+        let port = TcpListener::bind((Ipv4Addr::UNSPECIFIED, 0))?.local_addr()?.port();
+        if !allocated.contains(&port) {
+            return Ok(port);
+        }
+    }
+}
+
+fn allocate_port(
+    name: &str,
+    configured: Option<u16>,
+    default: u16,
+    allocated: &mut Vec<u16>
+) -> Result<u16> {
+    let port = match configured {
+        Some(port) if allocated.contains(&port) || !is_free(port) => {
+            return Err(CarpError::PortInUse { name: name.to_string(), port });
+        }
+        Some(port) => port,
+        None if !allocated.contains(&default) && is_free(default) => default,
+        None => any_free(allocated)?,
+    };
+    allocated.push(port);
+    Ok(port)
+}
+
+/// Resolves every port the network listens on and stores them in the config, so they are passed
+/// explicitly to the services
+pub fn allocate(config: &mut Config) -> Result<()> {
+    let mut allocated = Vec::new();
+    let node = &mut config.node;
+    node.rpc_port = Some(
+        allocate_port("node RPC", node.rpc_port, DEFAULT_NODE_RPC_PORT, &mut allocated)?
+    );
+    node.p2p_port = Some(
+        allocate_port("node p2p", node.p2p_port, DEFAULT_NODE_P2P_PORT, &mut allocated)?
+    );
+    node.prometheus_port = Some(
+        allocate_port(
+            "node Prometheus",
+            node.prometheus_port,
+            DEFAULT_NODE_PROMETHEUS_PORT,
+            &mut allocated
+        )?
+    );
+    let eth_rpc = &mut config.eth_rpc;
+    eth_rpc.rpc_port = Some(
+        allocate_port("eth-rpc", eth_rpc.rpc_port, DEFAULT_ETH_RPC_PORT, &mut allocated)?
+    );
+    Ok(())
+}
@@ -0,0 +1,164 @@
+use std::{
+    collections::VecDeque,
+    ffi::OsStr,
+    io::{ BufRead, BufReader },
+    os::unix::process::CommandExt,
+    process::{ Child, Command, ExitStatus, Stdio },
+};
+
+/// Number of stderr lines kept from a failed one-shot command
+const STDERR_TAIL_LINES: usize = 20;
+
+/// Spawns a service with stdout and stderr piped to be captured by carp. The child gets its own
+/// process group, so a Ctrl-C in the terminal reaches carp alone and it decides the order services
+/// are stopped in.
+pub fn generate_piped_child_process<B, I, S>(bin_name: B, args: I) -> Result<Child, std::io::Error>
+    where B: AsRef<OsStr>, I: IntoIterator<Item = S>, S: AsRef<OsStr>
+{
+    Command::new(bin_name)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()
+}
+
+fn send_signal(id: u32, signal: libc::c_int) -> Result<(), std::io::Error> {
+    let pid = libc::pid_t
+        ::try_from(id)
+        .map_err(|_| std::io::Error::other(format!("invalid pid {id}")))?;
+    // SAFETY: kill(2) has no memory safety requirements
+    if unsafe { libc::kill(pid, signal) } == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+/// Signals the process group a service leads, which includes whatever it spawned itself
+fn signal_group(id: u32, signal: libc::c_int) -> Result<(), std::io::Error> {
+    let pgid = libc::pid_t
+        ::try_from(id)
+        .map_err(|_| std::io::Error::other(format!("invalid pid {id}")))?;
+    // SAFETY: killpg(2) has no memory safety requirements
+    if unsafe { libc::killpg(pgid, signal) } == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+/// Asks the service to shut down with SIGTERM
+pub fn kill_process(id: u32) -> Result<(), std::io::Error> {
+    signal_group(id, libc::SIGTERM)
+}
+
+pub fn force_kill_process(id: u32) -> Result<(), std::io::Error> {
+    signal_group(id, libc::SIGKILL)
+}
+
+/// Asks another carp to shut its network down, the way Ctrl-C does
+pub fn terminate_process(id: u32) -> Result<(), std::io::Error> {
+    send_signal(id, libc::SIGTERM)
+}
+
+/// Runs `command` in a session of its own, so it outlives the terminal it was started from
+pub fn detach(command: &mut Command) -> &mut Command {
+    // SAFETY: setsid(2) is async-signal-safe, nothing else runs between fork and exec
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setsid() == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        })
+    }
+}
+
+pub fn is_process_alive(id: u32) -> bool {
+    // EPERM means the process exists but belongs to someone else
+    let exists = match send_signal(id, 0) {
+        Ok(()) => true,
+        Err(err) => err.raw_os_error() == Some(libc::EPERM),
+    };
+    exists && !is_zombie(id)
+}
+
+/// Whether `id` is alive and still the process that started at `start_time`, rather than a later
+/// one that was given the same pid. Without a recorded start time only liveness is checked.
+pub fn is_same_process(id: u32, start_time: Option<u64>) -> bool {
+    is_process_alive(id) && (start_time.is_none() || self::start_time(id) == start_time)
+}
+
+/// When the process started, in clock ticks since boot. Only known where `/proc` exists.
+pub fn start_time(id: u32) -> Option<u64> {
+    // Field 22 of stat, the fields after the command name start at field 3
+    stat_fields(id)?.get(22 - 3)?.parse().ok()
+}
+
+/// A process that exited still takes signals until its parent reaps it. Only detected where
+/// `/proc` exists.
+fn is_zombie(id: u32) -> bool {
+    stat_fields(id).is_some_and(|fields| fields.first().is_some_and(|state| state == "Z"))
+}
+
+/// The fields of `/proc/<id>/stat` that follow the command name
+fn stat_fields(id: u32) -> Option<Vec<String>> {
+    let stat = std::fs::read_to_string(format!("/proc/{id}/stat")).ok()?;
+    // The command name may itself contain parentheses
+    let (_, rest) = stat.rsplit_once(')')?;
+    Some(rest.split_whitespace().map(str::to_string).collect())
+}
+
+/// Runs `command` to completion, keeping the last stderr lines for error reports. With `echo` the
+/// lines are also forwarded to our own stderr.
+pub fn run_capturing_stderr(
+    command: &mut Command,
+    echo: bool
+) -> Result<(ExitStatus, String), std::io::Error> {
+    let mut child = command.stderr(Stdio::piped()).spawn()?;
+    let mut tail = VecDeque::with_capacity(STDERR_TAIL_LINES);
+
+    if let Some(stderr) = child.stderr.take() {
+        for line in BufReader::new(stderr).lines() {
+            let line = line?;
+            if echo {
+                eprintln!("{line}");
+            }
+            if tail.len() == STDERR_TAIL_LINES {
+                tail.pop_front();
+            }
+            tail.push_back(line);
+        }
+    }
+
+    let status = child.wait()?;
+    Ok((status, Vec::from(tail).join("\n")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identifies_process_by_start_time() {
+        let pid = std::process::id();
+        let started = start_time(pid);
+        assert!(started.is_some());
+        assert_eq!(start_time(pid), started);
+
+        assert!(is_same_process(pid, started));
+        assert!(is_same_process(pid, None));
+        // A process with our pid that started later is a different one
+        assert!(!is_same_process(pid, started.map(|ticks| ticks + 1)));
+    }
+
+    #[test]
+    fn exited_process_is_not_running() {
+        let mut child = Command::new("true").spawn().unwrap();
+        let (pid, started) = (child.id(), start_time(child.id()));
+        child.wait().unwrap();
+        assert!(!is_same_process(pid, started));
+    }
+}
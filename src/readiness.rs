@@ -0,0 +1,25 @@
+use serde_json::json;
+
+use crate::{ blocks, rpc::{ self, hex_to_u64 } };
+
+/// The node is ready once its RPC answers `system_health` and the first block has been authored.
+/// With `author_first_block` carp authors it, for nodes that only seal on request.
+pub fn node_ready(port: u16, author_first_block: bool) -> bool {
+    if rpc::call(port, "system_health", json!([])).is_err() {
+        return false;
+    }
+    let best_block = || {
+        rpc::call(port, "chain_getHeader", json!([]))
+            .ok()
+            .and_then(|header| hex_to_u64(&header["number"]))
+    };
+    if author_first_block && best_block() == Some(0) {
+        let _ = blocks::create_block(port, true);
+    }
+    best_block().is_some_and(|number| number >= 1)
+}
+
+/// eth-rpc is ready once `eth_blockNumber` answers
+pub fn eth_rpc_ready(port: u16) -> bool {
+    rpc::call(port, "eth_blockNumber", json!([])).is_ok_and(|number| hex_to_u64(&number).is_some())
+}
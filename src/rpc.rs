@@ -0,0 +1,60 @@
+use std::{ io::{ Read, Write }, net::{ SocketAddr, TcpStream }, time::Duration };
+
+use serde_json::{ Value, json };
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Minimal JSON-RPC over HTTP client for talking to the local node and eth-rpc
+pub fn call(port: u16, method: &str, params: Value) -> Result<Value, std::io::Error> {
+    let body = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params }).to_string();
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+    let mut stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    write!(
+        stream,
+        "POST / HTTP/1.1\r\nHost: 127.0.0.1:{port}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+    let response = String::from_utf8_lossy(&response);
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| invalid("malformed HTTP response"))?;
+
+    let chunked = head
+        .lines()
+        .any(|line| line.to_ascii_lowercase().starts_with("transfer-encoding: chunked"));
+    let body = if chunked { dechunk(body)? } else { body.to_string() };
+
+    let mut value: Value = serde_json::from_str(&body).map_err(|err| invalid(&err.to_string()))?;
+    if let Some(error) = value.get("error") {
+        return Err(std::io::Error::other(format!("{method} failed: {error}")));
+    }
+    Ok(value["result"].take())
+}
+
+fn dechunk(mut body: &str) -> Result<String, std::io::Error> {
+    let mut out = String::new();
+    loop {
+        let (size, rest) = body.split_once("\r\n").ok_or_else(|| invalid("malformed chunk"))?;
+        let size = usize::from_str_radix(size.trim(), 16).map_err(|_| invalid("malformed chunk"))?;
+        if size == 0 {
+            return Ok(out);
+        }
+        out.push_str(rest.get(..size).ok_or_else(|| invalid("truncated chunk"))?);
+        body = rest.get(size + 2..).unwrap_or_default();
+    }
+}
+
+fn invalid(msg: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// Parses a `0x` prefixed hex quantity such as a block number
+pub fn hex_to_u64(value: &Value) -> Option<u64> {
+    u64::from_str_radix(value.as_str()?.trim_start_matches("0x"), 16).ok()
+}
@@ -0,0 +1,503 @@
+use std::{
+    fs,
+    io::Read,
+    path::{ Path, PathBuf },
+    process::{ Command, Stdio },
+    time::UNIX_EPOCH,
+};
+
+use blake2::{ Blake2b512, Digest };
+use ruzstd::decoding::StreamingDecoder;
+use serde_json::Value;
+
+use crate::{
+    account::encode_hex,
+    config::{ Config, RuntimeBuildConfig },
+    error::{ CarpError, Result },
+    process::run_capturing_stderr,
+};
+
+/// Prefix substrate puts in front of zstd compressed code blobs
+const ZSTD_PREFIX: [u8; 8] = [0x52, 0xbc, 0x53, 0x76, 0x46, 0xdb, 0x8e, 0x05];
+/// Same limit the node applies when decompressing a code blob
+const CODE_BLOB_BOMB_LIMIT: u64 = 50 * 1024 * 1024;
+const WASM_MAGIC: &[u8] = b"\0asm";
+const VERSION_SECTION: &str = "runtime_version";
+const COMPRESSED_SUFFIX: &str = ".compact.compressed.wasm";
+/// Written next to a built runtime, records the sources it was built from
+const FINGERPRINT_FILE: &str = ".carp-fingerprint";
+
+/// Version the runtime embeds in its `runtime_version` custom section
+pub struct RuntimeVersion {
+    pub spec_name: String,
+    pub impl_name: String,
+    pub spec_version: u32,
+    pub impl_version: u32,
+    pub transaction_version: u32,
+}
+
+pub struct Runtime {
+    pub path: PathBuf,
+    /// File name without the `.wasm` or `.compact.compressed.wasm` suffix
+    pub name: String,
+    pub version: std::result::Result<RuntimeVersion, String>,
+}
+
+impl Runtime {
+    fn is_compressed(&self) -> bool {
+        self.path.to_string_lossy().ends_with(COMPRESSED_SUFFIX)
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        self.name == name || self.version.as_ref().is_ok_and(|version| version.spec_name == name)
+    }
+
+    fn describe(&self) -> String {
+        let file = self.path.file_name().unwrap_or_default().to_string_lossy();
+        match &self.version {
+            Ok(version) => format!("{file} ({} v{})", version.spec_name, version.spec_version),
+            Err(reason) => format!("{file} (invalid: {reason})"),
+        }
+    }
+}
+
+/// Reads the runtime version of a plain or compressed wasm blob
+pub fn read_version(path: &Path) -> std::result::Result<RuntimeVersion, String> {
+    let blob = fs::read(path).map_err(|err| format!("could not read {}: {}", path.display(), err))?;
+    let code = decompress(blob)?;
+    if !code.starts_with(WASM_MAGIC) {
+        return Err("not a wasm module".to_string());
+    }
+    let section = custom_section(&code, VERSION_SECTION)?.ok_or_else(|| {
+        format!("no `{VERSION_SECTION}` section, not a substrate runtime")
+    })?;
+    decode_version(section).ok_or_else(|| format!("malformed `{VERSION_SECTION}` section"))
+}
+
+fn decompress(blob: Vec<u8>) -> std::result::Result<Vec<u8>, String> {
+    let Some(compressed) = blob.strip_prefix(&ZSTD_PREFIX) else {
+        return Ok(blob);
+    };
+    let decoder = StreamingDecoder::new(compressed).map_err(|err| {
+        format!("invalid compressed blob: {err}")
+    })?;
+    let mut code = Vec::new();
+    decoder
+        .take(CODE_BLOB_BOMB_LIMIT + 1)
+        .read_to_end(&mut code)
+        .map_err(|err| format!("invalid compressed blob: {err}"))?;
+    if code.len() as u64 > CODE_BLOB_BOMB_LIMIT {
+        return Err(format!("decompresses to more than {CODE_BLOB_BOMB_LIMIT} bytes"));
+    }
+    Ok(code)
+}
+
+/// Walks the module's sections looking for the custom section `name`
+fn custom_section<'a>(code: &'a [u8], name: &str) -> std::result::Result<Option<&'a [u8]>, String> {
+    let truncated = || "truncated wasm module".to_string();
+    // Magic and version
+    let mut input = Input(code.get(8..).ok_or_else(truncated)?);
+    while !input.0.is_empty() {
+        let id = input.byte().ok_or_else(truncated)?;
+        let len = input.leb128().ok_or_else(truncated)?;
+        let mut section = Input(input.take(len as usize).ok_or_else(truncated)?);
+        if id != 0 {
+            continue;
+        }
+        let name_len = section.leb128().ok_or_else(truncated)?;
+        if section.take(name_len as usize).ok_or_else(truncated)? == name.as_bytes() {
+            return Ok(Some(section.0));
+        }
+    }
+    Ok(None)
+}
+
+/// SCALE encoded `sp_version::RuntimeVersion`
+fn decode_version(section: &[u8]) -> Option<RuntimeVersion> {
+    let mut input = Input(section);
+    let spec_name = input.string()?;
+    let impl_name = input.string()?;
+    let _authoring_version = input.u32()?;
+    let spec_version = input.u32()?;
+    let impl_version = input.u32()?;
+    // Api ids and versions, 8 + 4 bytes each
+    let apis = input.compact()?;
+    input.take(apis.checked_mul(12)? as usize)?;
+    let transaction_version = input.u32()?;
+    Some(RuntimeVersion { spec_name, impl_name, spec_version, impl_version, transaction_version })
+}
+
+struct Input<'a>(&'a [u8]);
+
+impl<'a> Input<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(head)
+    }
+
+    fn byte(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn leb128(&mut self) -> Option<u32> {
+        let mut value = 0u32;
+        for shift in (0..35).step_by(7) {
+            let byte = self.byte()?;
+            value |= u32::from(byte & 0x7f).checked_shl(shift)?;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    fn compact(&mut self) -> Option<u32> {
+        let first = self.0.first()?;
+        match first & 0b11 {
+            0 => Some(u32::from(self.byte()? >> 2)),
+            1 => Some(u32::from(u16::from_le_bytes(self.take(2)?.try_into().ok()?) >> 2)),
+            2 => Some(self.u32()? >> 2),
+            // Big integers, never a realistic length
+            _ => None,
+        }
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let len = self.compact()?;
+        String::from_utf8(self.take(len as usize)?.to_vec()).ok()
+    }
+}
+
+/// What `cargo metadata` tells us about the runtime package
+struct BuildTarget {
+    package: String,
+    workspace_root: PathBuf,
+    target_dir: PathBuf,
+}
+
+fn build_target(manifest: &Path, package: Option<&str>) -> Result<BuildTarget> {
+    let output = Command::new("cargo")
+        .args(["metadata", "--no-deps", "--offline", "--format-version", "1", "--manifest-path"])
+        .arg(manifest)
+        .stdin(Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(CarpError::Runtime(format!("cargo metadata failed\n{}", stderr.trim_end())));
+    }
+    let metadata: Value = serde_json
+        ::from_slice(&output.stdout)
+        .map_err(|err| CarpError::Runtime(format!("invalid cargo metadata: {err}")))?;
+
+    // Without an explicit name, the package whose manifest we were pointed at
+    let manifest = fs::canonicalize(manifest)?;
+    let package = metadata["packages"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find(|candidate| {
+            match package {
+                Some(package) => candidate["name"] == package,
+                None => candidate["manifest_path"].as_str().map(Path::new) == Some(&manifest),
+            }
+        })
+        .and_then(|package| package["name"].as_str())
+        .ok_or_else(|| {
+            CarpError::Runtime(
+                format!("package {} not found", package.unwrap_or(&manifest.to_string_lossy()))
+            )
+        })?;
+
+    let path = |key: &str| metadata[key].as_str().map(PathBuf::from);
+    match (path("workspace_root"), path("target_directory")) {
+        (Some(workspace_root), Some(target_dir)) => {
+            Ok(BuildTarget { package: package.to_string(), workspace_root, target_dir })
+        }
+        _ => Err(CarpError::Runtime("cargo metadata has no workspace root".to_string())),
+    }
+}
+
+/// Hash of the path, size and modification time of every source file in the workspace
+fn fingerprint(target: &BuildTarget) -> Result<String> {
+    fn walk(dir: &Path, target_dir: &Path, hasher: &mut Blake2b512) -> std::io::Result<()> {
+        let mut entries = fs::read_dir(dir)?.collect::<std::io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let path = entry.path();
+            // Build output and hidden directories such as .git
+            if path == target_dir || entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let metadata = entry.metadata()?;
+            if metadata.is_dir() {
+                walk(&path, target_dir, hasher)?;
+                continue;
+            }
+            let modified = metadata
+                .modified()?
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos();
+            hasher.update(path.to_string_lossy().as_bytes());
+            hasher.update(metadata.len().to_le_bytes());
+            hasher.update(modified.to_le_bytes());
+        }
+        Ok(())
+    }
+
+    let mut hasher = Blake2b512::new();
+    hasher.update(target.package.as_bytes());
+    walk(&target.workspace_root, &target.target_dir, &mut hasher)?;
+    Ok(encode_hex(&hasher.finalize()[..16]))
+}
+
+/// Builds the runtime package unless its sources are unchanged since the last build, returning
+/// the compact compressed blob
+fn build_runtime(config: &Config, build: &RuntimeBuildConfig) -> Result<PathBuf> {
+    let manifest = config.resolve_user_path(&build.path).join("Cargo.toml");
+    let target = build_target(&manifest, build.package.as_deref())?;
+    let wbuild = target.target_dir.join("release").join("wbuild").join(&target.package);
+    let artifact = wbuild.join(format!("{}{COMPRESSED_SUFFIX}", target.package.replace('-', "_")));
+    let stamp = wbuild.join(FINGERPRINT_FILE);
+
+    let sources = fingerprint(&target)?;
+    if artifact.is_file() && fs::read_to_string(&stamp).is_ok_and(|stamp| stamp == sources) {
+        eprintln!("{} is up to date", target.package);
+        return Ok(artifact);
+    }
+
+    eprintln!("Building runtime {}...", target.package);
+    let mut command = Command::new("cargo");
+    command
+        .args(["build", "--release", "-p", &target.package, "--manifest-path"])
+        .arg(&manifest)
+        .stdout(Stdio::null());
+    let (status, stderr_tail) = run_capturing_stderr(&mut command, true)?;
+    if !status.success() {
+        return Err(
+            CarpError::Runtime(
+                format!("building {} failed with {}\n{}", target.package, status, stderr_tail)
+            )
+        );
+    }
+    if !artifact.is_file() {
+        return Err(
+            CarpError::Runtime(
+                format!(
+                    "{} built but {} is missing, is it a runtime with a wasm builder?",
+                    target.package,
+                    artifact.display()
+                )
+            )
+        );
+    }
+    // Taken after the build, which may have written a Cargo.lock
+    fs::write(&stamp, fingerprint(&target)?)?;
+    Ok(artifact)
+}
+
+/// Every `*.wasm` blob in the runtimes directory, sorted by file name
+pub fn discover(config: &Config) -> Result<Vec<Runtime>> {
+    let dir = config.resolve(&config.runtime.dir);
+    let entries = fs
+        ::read_dir(&dir)
+        .map_err(|err| CarpError::Runtime(format!("could not read {}: {}", dir.display(), err)))?;
+
+    let mut runtimes = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let Some(name) = file_name
+            .strip_suffix(COMPRESSED_SUFFIX)
+            .or_else(|| file_name.strip_suffix(".wasm")) else {
+            continue;
+        };
+        let name = name.to_string();
+        let version = read_version(&path);
+        runtimes.push(Runtime { path, name, version });
+    }
+    runtimes.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(runtimes)
+}
+
+/// Picks the runtime the network is built from and stores it in `runtime.path`. An explicit
+/// `runtime.path` wins, otherwise `runtime.name` selects a blob from `runtime.dir` by file name or
+/// spec_name. A runtime available in both forms resolves to the compact compressed blob.
+pub fn select(config: &mut Config) -> Result<()> {
+    if let Some(build) = &config.runtime.build {
+        let path = build_runtime(config, build)?;
+        let version = read_version(&path).map_err(|reason| {
+            CarpError::Runtime(format!("{} is not a usable runtime: {}", path.display(), reason))
+        })?;
+        print_selected(&path, &version);
+        config.runtime.path = Some(path);
+        return Ok(());
+    }
+
+    if let Some(path) = &config.runtime.path {
+        let path = config.resolve(path);
+        let version = read_version(&path).map_err(|reason| {
+            CarpError::Runtime(format!("{} is not a usable runtime: {}", path.display(), reason))
+        })?;
+        print_selected(&path, &version);
+        config.runtime.path = Some(path);
+        return Ok(());
+    }
+
+    let dir = config.resolve(&config.runtime.dir);
+    let runtimes = discover(config)?;
+    let mut candidates: Vec<&Runtime> = match &config.runtime.name {
+        Some(name) => runtimes.iter().filter(|runtime| runtime.matches(name)).collect(),
+        None => runtimes.iter().filter(|runtime| runtime.version.is_ok()).collect(),
+    };
+    // A runtime present in both forms counts once, as its compact compressed blob
+    let compressed: Vec<&str> = candidates
+        .iter()
+        .filter(|runtime| runtime.is_compressed())
+        .map(|&runtime| runtime.name.as_str())
+        .collect();
+    candidates.retain(|runtime| {
+        runtime.is_compressed() || !compressed.contains(&runtime.name.as_str())
+    });
+
+    let available = runtimes.iter().map(Runtime::describe).collect::<Vec<_>>().join(", ");
+    let runtime = match (candidates.as_slice(), &config.runtime.name) {
+        ([runtime], _) => runtime,
+        ([], Some(name)) => {
+            let dir = dir.display();
+            let message = format!("no runtime named `{name}` in {dir}, available: {available}");
+            return Err(CarpError::Runtime(message));
+        }
+        ([], None) => {
+            return Err(CarpError::Runtime(format!("no *.wasm runtimes in {}", dir.display())));
+        }
+        (_, _) => {
+            return Err(
+                CarpError::Runtime(
+                    format!(
+                        "{} contains several runtimes, choose one with `runtime.name` or \
+                         `--runtime`: {available}",
+                        dir.display()
+                    )
+                )
+            );
+        }
+    };
+
+    let version = runtime.version.as_ref().map_err(|reason| {
+        CarpError::Runtime(
+            format!("{} is not a usable runtime: {}", runtime.path.display(), reason)
+        )
+    })?;
+    print_selected(&runtime.path, version);
+    config.runtime.path = Some(runtime.path.clone());
+    Ok(())
+}
+
+fn print_selected(path: &Path, version: &RuntimeVersion) {
+    eprintln!("Using runtime {} ({} v{})", path.display(), version.spec_name, version.spec_version);
+}
+
+pub fn print_runtimes(config: &Config) -> Result<()> {
+    for runtime in discover(config)? {
+        let file = runtime.path.file_name().unwrap_or_default().to_string_lossy();
+        match &runtime.version {
+            Ok(version) => {
+                println!(
+                    "{file}: {} v{} ({} v{}, transaction v{})",
+                    version.spec_name,
+                    version.spec_version,
+                    version.impl_name,
+                    version.impl_version,
+                    version.transaction_version
+                );
+            }
+            Err(reason) => println!("{file}: invalid, {reason}"),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn westend() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("runtimes/westend.wasm")
+    }
+
+    fn westend_code() -> Vec<u8> {
+        decompress(fs::read(westend()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn reads_compressed_runtime_version() {
+        let version = read_version(&westend()).unwrap();
+        assert_eq!(version.spec_name, "westmint");
+        assert_eq!(version.spec_version, 1017002);
+        assert_eq!(version.transaction_version, 16);
+    }
+
+    #[test]
+    fn decompress_passes_plain_code_through() {
+        let code = westend_code();
+        assert!(code.starts_with(WASM_MAGIC));
+        assert_eq!(decompress(code.clone()).unwrap(), code);
+    }
+
+    #[test]
+    fn rejects_corrupt_compressed_blob() {
+        let mut blob = ZSTD_PREFIX.to_vec();
+        blob.extend_from_slice(b"not zstd at all");
+        assert!(decompress(blob).unwrap_err().starts_with("invalid compressed blob"));
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        let path = std::env::temp_dir().join(format!("carp-bad-magic-{}.wasm", std::process::id()));
+        fs::write(&path, b"\0elf\x01\0\0\0").unwrap();
+        let result = read_version(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(result.err().as_deref(), Some("not a wasm module"));
+    }
+
+    #[test]
+    fn rejects_truncated_module() {
+        let code = westend_code();
+        assert!(custom_section(&code[..4], VERSION_SECTION).is_err());
+        // Cut inside the first section's payload
+        let truncated = Err("truncated wasm module".to_string());
+        assert_eq!(custom_section(&code[..12], VERSION_SECTION), truncated);
+    }
+
+    #[test]
+    fn rejects_truncated_version() {
+        let code = westend_code();
+        let section = custom_section(&code, VERSION_SECTION).unwrap().unwrap();
+        assert!(decode_version(section).is_some());
+        // Only the trailing state_version byte may be missing
+        for len in 0..section.len() - 1 {
+            assert!(decode_version(&section[..len]).is_none(), "decoded {len} bytes");
+        }
+    }
+
+    #[test]
+    fn decodes_leb128_and_compact() {
+        assert_eq!(Input(&[0xe5, 0x8e, 0x26]).leb128(), Some(624_485));
+        assert_eq!(Input(&[0x80, 0x80]).leb128(), None);
+        assert_eq!(Input(&[0xff, 0xff, 0xff, 0xff, 0xff, 0x01]).leb128(), None);
+        assert_eq!(Input(&[0x04]).compact(), Some(1));
+        assert_eq!(Input(&[0x15, 0x01]).compact(), Some(69));
+        assert_eq!(Input(&[0x02, 0x00, 0x01, 0x00]).compact(), Some(16_384));
+        assert_eq!(Input(&[0x03, 0x00]).compact(), None);
+        assert_eq!(Input(&[0x01]).compact(), None);
+    }
+}
@@ -0,0 +1,120 @@
+use std::{ fs, io::ErrorKind, path::PathBuf };
+
+use serde::{ Deserialize, Serialize };
+
+use crate::{ config::Config, error::Result, process };
+
+const STATE_FILE: &str = "state.json";
+
+/// What a running `carp up` records in the network directory, so other carp invocations can
+/// find the network
+#[derive(Serialize, Deserialize)]
+pub struct State {
+    /// The carp process supervising the services
+    pub pid: u32,
+    /// Tells the recorded processes apart from later ones that reuse their pids
+    #[serde(default)]
+    pub start_time: Option<u64>,
+    pub detached: bool,
+    /// Set once every service is ready
+    pub ready: bool,
+    pub network_dir: PathBuf,
+    pub ports: Ports,
+    pub services: Vec<ServiceState>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Ports {
+    pub node_rpc: u16,
+    pub node_p2p: u16,
+    pub node_prometheus: u16,
+    pub eth_rpc: u16,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ServiceState {
+    pub name: String,
+    pub pid: u32,
+    #[serde(default)]
+    pub start_time: Option<u64>,
+}
+
+impl State {
+    pub fn new(config: &Config, detached: bool, ready: bool, services: &[(&str, u32)]) -> Self {
+        let pid = std::process::id();
+        State {
+            pid,
+            start_time: process::start_time(pid),
+            detached,
+            ready,
+            network_dir: config.network_dir(),
+            ports: Ports {
+                node_rpc: config.node_rpc_port(),
+                node_p2p: config.node_p2p_port(),
+                node_prometheus: config.node_prometheus_port(),
+                eth_rpc: config.eth_rpc_port(),
+            },
+            services: services
+                .iter()
+                .map(|(name, pid)| ServiceState {
+                    name: name.to_string(),
+                    pid: *pid,
+                    start_time: process::start_time(*pid),
+                })
+                .collect(),
+        }
+    }
+
+    /// Whether the recorded carp is still running
+    pub fn is_running(&self) -> bool {
+        process::is_same_process(self.pid, self.start_time)
+    }
+
+    /// Points the config at the ports the running network actually uses
+    pub fn apply_ports(&self, config: &mut Config) {
+        config.node.rpc_port = Some(self.ports.node_rpc);
+        config.node.p2p_port = Some(self.ports.node_p2p);
+        config.node.prometheus_port = Some(self.ports.node_prometheus);
+        config.eth_rpc.rpc_port = Some(self.ports.eth_rpc);
+    }
+}
+
+impl ServiceState {
+    pub fn is_running(&self) -> bool {
+        process::is_same_process(self.pid, self.start_time)
+    }
+}
+
+fn path(config: &Config) -> PathBuf {
+    config.network_dir().join(STATE_FILE)
+}
+
+/// Written to a temporary file first, the state is polled while it changes
+pub fn write(config: &Config, state: &State) -> Result<()> {
+    let path = path(config);
+    let partial = path.with_extension("json.partial");
+    fs::write(&partial, serde_json::to_vec_pretty(state).map_err(std::io::Error::other)?)?;
+    fs::rename(partial, path)?;
+    Ok(())
+}
+
+/// `None` when no network was started, or its state file is unreadable
+pub fn read(config: &Config) -> Result<Option<State>> {
+    let contents = match fs::read(path(config)) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            return Ok(None);
+        }
+        Err(err) => {
+            return Err(err.into());
+        }
+    };
+    Ok(serde_json::from_slice(&contents).ok())
+}
+
+pub fn remove(config: &Config) -> Result<()> {
+    match fs::remove_file(path(config)) {
+        Err(err) if err.kind() != ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,366 @@
+use std::{ process::{ Child, ExitStatus }, time::{ Duration, Instant } };
+
+use crate::{
+    config::{ RestartConfig, RestartPolicy },
+    error::{ CarpError, Result },
+    process::{ force_kill_process, kill_process },
+};
+
+/// A service that ran this long before exiting gets its restart counter reset
+const STABLE_UPTIME: Duration = Duration::from_secs(60);
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Grace period of services that don't set one
+const DEFAULT_STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+type SpawnFn<'a> = Box<dyn Fn() -> std::io::Result<Child> + 'a>;
+
+pub struct Service<'a> {
+    name: String,
+    spawn: SpawnFn<'a>,
+    restart: RestartConfig,
+    critical: bool,
+    depends_on: Option<String>,
+    stop_timeout: Duration,
+    child: Option<Child>,
+    started_at: Instant,
+    restarts: u32,
+    restart_at: Option<Instant>,
+    last_status: Option<ExitStatus>,
+    given_up: bool,
+}
+
+impl<'a> Service<'a> {
+    pub fn new<F>(name: &str, restart: RestartConfig, spawn: F) -> Self
+        where F: Fn() -> std::io::Result<Child> + 'a
+    {
+        Service {
+            name: name.to_string(),
+            spawn: Box::new(spawn),
+            restart,
+            critical: false,
+            depends_on: None,
+            stop_timeout: DEFAULT_STOP_TIMEOUT,
+            child: None,
+            started_at: Instant::now(),
+            restarts: 0,
+            restart_at: None,
+            last_status: None,
+            given_up: false,
+        }
+    }
+
+    /// The whole network is torn down when a critical service stays down
+    pub fn critical(mut self) -> Self {
+        self.critical = true;
+        self
+    }
+
+    /// The service is stopped when `name` stays down
+    pub fn depends_on(mut self, name: &str) -> Self {
+        self.depends_on = Some(name.to_string());
+        self
+    }
+
+    /// How long the service may take to exit after SIGTERM before it is killed
+    pub fn stop_timeout(mut self, timeout: Duration) -> Self {
+        self.stop_timeout = timeout;
+        self
+    }
+
+    fn start(&mut self) -> Result<()> {
+        self.child = Some((self.spawn)().map_err(|err| CarpError::spawn(&self.name, err))?);
+        self.started_at = Instant::now();
+        self.restart_at = None;
+        Ok(())
+    }
+
+    /// Sends SIGTERM and reaps the process, escalating to SIGKILL once the grace period passes.
+    /// Returns whether it had to be force-killed.
+    fn stop(&mut self) -> bool {
+        let Some(mut child) = self.child.take() else {
+            return false;
+        };
+        println!("Stopping {} ({})", self.name, child.id());
+        if let Err(err) = kill_process(child.id()) {
+            eprintln!("{} failed to be stopped: {}", self.name, err);
+        }
+
+        let deadline = Instant::now() + self.stop_timeout;
+        loop {
+            match child.try_wait() {
+                Ok(Some(_)) => {
+                    return false;
+                }
+                Ok(None) if Instant::now() < deadline => std::thread::sleep(STOP_POLL_INTERVAL),
+                Ok(None) => {
+                    break;
+                }
+                Err(err) => {
+                    eprintln!("{} failed to be waited on: {}", self.name, err);
+                    break;
+                }
+            }
+        }
+
+        println!(
+            "{} did not exit within {}s, killing it",
+            self.name,
+            self.stop_timeout.as_secs()
+        );
+        if let Err(err) = force_kill_process(child.id()) {
+            eprintln!("{} failed to be killed: {}", self.name, err);
+        }
+        let _ = child.wait();
+        true
+    }
+
+    fn should_restart(&self, status: Option<ExitStatus>) -> bool {
+        let failed = status.is_none_or(|status| !status.success());
+        let wanted = match self.restart.policy {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure => failed,
+            RestartPolicy::Always => true,
+        };
+        wanted && self.restarts < self.restart.max_restarts
+    }
+
+    fn backoff(&self) -> Duration {
+        let delay = self.restart.backoff_ms.saturating_mul(1 << self.restarts.min(16));
+        Duration::from_millis(delay.min(self.restart.max_backoff_ms))
+    }
+
+    /// Decides what happens after the process exited, `status` is `None` when it failed to spawn
+    fn exited(&mut self, status: Option<ExitStatus>) {
+        self.last_status = status;
+        if self.started_at.elapsed() >= STABLE_UPTIME {
+            self.restarts = 0;
+        }
+
+        if self.should_restart(status) {
+            let backoff = self.backoff();
+            println!(
+                "Restarting {} in {}ms (attempt {}/{})",
+                self.name,
+                backoff.as_millis(),
+                self.restarts + 1,
+                self.restart.max_restarts
+            );
+            self.restarts += 1;
+            self.restart_at = Some(Instant::now() + backoff);
+        } else {
+            println!("{} will not be restarted", self.name);
+            self.given_up = true;
+        }
+    }
+
+    fn crashed(&self) -> CarpError {
+        CarpError::ServiceCrashed { name: self.name.clone(), status: self.last_status }
+    }
+}
+
+#[derive(Default)]
+pub struct Supervisor<'a> {
+    services: Vec<Service<'a>>,
+}
+
+impl<'a> Supervisor<'a> {
+    /// Spawns the service and starts supervising it
+    pub fn start(&mut self, mut service: Service<'a>) -> Result<()> {
+        service.start()?;
+        self.services.push(service);
+        Ok(())
+    }
+
+    pub fn pids(&self) -> Vec<(&str, u32)> {
+        self.services
+            .iter()
+            .filter_map(|service| Some((service.name.as_str(), service.child.as_ref()?.id())))
+            .collect()
+    }
+
+    /// Checks every service once, restarting the ones that are due. Returns whether any process
+    /// was respawned, or an error when a critical service stayed down.
+    pub fn poll(&mut self) -> Result<bool> {
+        let mut respawned = false;
+
+        for service in self.services.iter_mut() {
+            if let Some(child) = service.child.as_mut() {
+                if let Some(status) = child.try_wait()? {
+                    println!("{} exited with {}", service.name, status);
+                    service.child = None;
+                    service.exited(Some(status));
+                }
+            } else if
+                !service.given_up &&
+                service.restart_at.is_some_and(|at| at <= Instant::now())
+            {
+                match service.start() {
+                    Ok(()) => {
+                        respawned = true;
+                    }
+                    Err(err) => {
+                        eprintln!("{} failed to start: {}", service.name, err);
+                        service.exited(None);
+                    }
+                }
+            }
+        }
+
+        self.stop_dependents();
+
+        match self.services.iter().find(|service| service.critical && service.given_up) {
+            Some(service) => Err(service.crashed()),
+            None => Ok(respawned),
+        }
+    }
+
+    /// Fails with the last exit status of `name` once it stays down for good
+    pub fn ensure_up(&self, name: &str) -> Result<()> {
+        match self.services.iter().find(|service| service.name == name && service.given_up) {
+            Some(service) => Err(service.crashed()),
+            None => Ok(()),
+        }
+    }
+
+    fn stop_dependents(&mut self) {
+        loop {
+            let down: Vec<String> = self.services
+                .iter()
+                .filter(|service| service.given_up)
+                .map(|service| service.name.clone())
+                .collect();
+            let Some(dependent) = self.services
+                .iter_mut()
+                .find(|service| {
+                    !service.given_up &&
+                        service.depends_on.as_ref().is_some_and(|dep| down.contains(dep))
+                }) else {
+                return;
+            };
+            println!("Tearing down {}, a service it depends on stayed down", dependent.name);
+            dependent.stop();
+            dependent.restart_at = None;
+            dependent.given_up = true;
+        }
+    }
+
+    /// Stops every service, dependents first, and returns the ones that had to be force-killed
+    pub fn stop_all(&mut self) -> Vec<String> {
+        let mut killed = Vec::new();
+        for service in self.services.iter_mut().rev() {
+            if service.stop() {
+                killed.push(service.name.clone());
+            }
+        }
+        killed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ os::unix::process::{ CommandExt, ExitStatusExt }, process::Command };
+
+    use super::*;
+
+    fn restart(policy: RestartPolicy) -> RestartConfig {
+        RestartConfig { policy, max_restarts: 3, backoff_ms: 100, max_backoff_ms: 1000 }
+    }
+
+    fn service<'a>(name: &str, restart: RestartConfig, program: &'a str) -> Service<'a> {
+        Service::new(name, restart, move || Command::new(program).process_group(0).spawn())
+    }
+
+    fn exit_code(code: i32) -> ExitStatus {
+        ExitStatus::from_raw(code << 8)
+    }
+
+    #[test]
+    fn restarts_according_to_policy() {
+        let never = service("never", restart(RestartPolicy::Never), "true");
+        assert!(!never.should_restart(Some(exit_code(1))));
+
+        let on_failure = service("on-failure", restart(RestartPolicy::OnFailure), "true");
+        assert!(on_failure.should_restart(Some(exit_code(1))));
+        assert!(!on_failure.should_restart(Some(exit_code(0))));
+        // Failing to spawn counts as a failure
+        assert!(on_failure.should_restart(None));
+
+        let mut always = service("always", restart(RestartPolicy::Always), "true");
+        assert!(always.should_restart(Some(exit_code(0))));
+        always.restarts = 3;
+        assert!(!always.should_restart(Some(exit_code(1))));
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_maximum() {
+        let mut service = service("node", restart(RestartPolicy::OnFailure), "true");
+        let delays: Vec<u128> = (0..6)
+            .map(|restarts| {
+                service.restarts = restarts;
+                service.backoff().as_millis()
+            })
+            .collect();
+        assert_eq!(delays, [100, 200, 400, 800, 1000, 1000]);
+
+        service.restarts = u32::MAX;
+        assert_eq!(service.backoff(), Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn stable_uptime_resets_the_restart_counter() {
+        let mut service = service("node", restart(RestartPolicy::OnFailure), "true");
+        service.restarts = 2;
+        service.started_at = Instant::now() - STABLE_UPTIME;
+        service.exited(Some(exit_code(1)));
+        assert_eq!(service.restarts, 1);
+        assert!(!service.given_up);
+
+        // A quick crash keeps counting, until the restarts run out
+        service.started_at = Instant::now();
+        service.exited(Some(exit_code(1)));
+        service.exited(Some(exit_code(1)));
+        assert_eq!(service.restarts, 3);
+        service.exited(Some(exit_code(1)));
+        assert!(service.given_up);
+        assert_eq!(service.last_status, Some(exit_code(1)));
+    }
+
+    #[test]
+    fn tears_down_dependents_of_a_service_that_stayed_down() {
+        let mut supervisor = Supervisor::default();
+        supervisor.start(service("node", restart(RestartPolicy::Never), "false")).unwrap();
+        let eth_rpc = RestartConfig { backoff_ms: 0, ..restart(RestartPolicy::OnFailure) };
+        supervisor.start(service("eth-rpc", eth_rpc, "false").depends_on("node")).unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+
+        // Both exit, eth-rpc is due for a restart when the node gives up
+        assert!(!supervisor.poll().unwrap());
+        let eth_rpc = &supervisor.services[1];
+        assert!(eth_rpc.given_up);
+        assert!(eth_rpc.restart_at.is_none());
+
+        assert!(!supervisor.poll().unwrap());
+        assert!(supervisor.services[1].child.is_none());
+        assert!(supervisor.pids().is_empty());
+
+        let Err(CarpError::ServiceCrashed { name, status }) = supervisor.ensure_up("node") else {
+            panic!("the node stayed down");
+        };
+        assert_eq!(name, "node");
+        assert_eq!(status, Some(exit_code(1)));
+    }
+
+    #[test]
+    fn critical_service_staying_down_fails_the_network() {
+        let mut supervisor = Supervisor::default();
+        let node = service("node", restart(RestartPolicy::Never), "false").critical();
+        supervisor.start(node).unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+
+        let Err(CarpError::ServiceCrashed { name, status }) = supervisor.poll() else {
+            panic!("the node staying down must fail the network");
+        };
+        assert_eq!(name, "node");
+        assert_eq!(status, Some(exit_code(1)));
+    }
+}
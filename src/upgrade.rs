@@ -0,0 +1,264 @@
+use std::{ fs, path::Path, time::Duration };
+
+use blake2::{ Blake2b256, Digest };
+use serde_json::json;
+use subxt::{
+    OnlineClient,
+    PolkadotConfig,
+    client::OnlineClientAtBlockImpl,
+    dynamic::{ self, At, Value },
+    extrinsics::ExtrinsicEvents,
+    ext::scale_value::ValueDef,
+    transactions::{ SubmittableTransaction, TransactionStatus },
+};
+use subxt_signer::{ SecretUri, sr25519::Keypair };
+
+use crate::{
+    account,
+    blocks::Sealer,
+    config::{ BlockProduction, Config },
+    error::{ CarpError, Result },
+    rpc,
+    runtime,
+};
+
+/// A parachain applies new code a few blocks after it was scheduled
+const CODE_UPDATED_TIMEOUT: Duration = Duration::from_secs(120);
+/// Upgrade transactions are included in the next block unless the node stopped authoring
+const INCLUSION_TIMEOUT: Duration = Duration::from_secs(60);
+/// Block time carp authors with while upgrading a network that doesn't author on its own
+const UPGRADE_BLOCK_TIME: Duration = Duration::from_secs(1);
+/// `twox128("Sudo") ++ twox128("Key")`
+const SUDO_KEY_STORAGE: &str = "5c0d1176a568c1f92944340dbfed9e9c530ebca703c85910e7164cb7d1c9e47b";
+
+type Client = OnlineClient<PolkadotConfig>;
+type Transaction = SubmittableTransaction<PolkadotConfig, OnlineClientAtBlockImpl<PolkadotConfig>>;
+
+fn failed(reason: impl ToString) -> CarpError {
+    CarpError::Upgrade(reason.to_string())
+}
+
+fn spec_version(port: u16) -> Result<u64> {
+    let version = rpc::call(port, "state_getRuntimeVersion", json!([])).map_err(|err| {
+        failed(format!("node RPC on port {port} is not reachable, is the network up? {err}"))
+    })?;
+    version["specVersion"].as_u64().ok_or_else(|| failed(format!("unexpected version {version}")))
+}
+
+/// Replaces the running network's runtime with `wasm`, either with `sudo(system.setCode)` or by
+/// authorizing its hash and applying it in an unsigned `system.applyAuthorizedUpgrade`. Both are
+/// signed with `upgrade.signer`, which must hold the chain's sudo key.
+pub fn upgrade(config: &Config, wasm: &Path, authorize: bool) -> Result<()> {
+    let signer = signer(&config.upgrade.signer)?;
+    let new = runtime::read_version(wasm).map_err(|reason| {
+        CarpError::Runtime(format!("{} is not a usable runtime: {}", wasm.display(), reason))
+    })?;
+    let code = fs::read(wasm)?;
+    let port = config.node_rpc_port();
+
+    let old_spec_version = spec_version(port)?;
+    if u64::from(new.spec_version) <= old_spec_version {
+        return Err(
+            failed(
+                format!(
+                    "{} has spec_version {} but the chain already runs {}, it must increase",
+                    wasm.display(),
+                    new.spec_version,
+                    old_spec_version
+                )
+            )
+        );
+    }
+
+    println!(
+        "Upgrading {} v{} to {} v{}...",
+        new.spec_name,
+        old_spec_version,
+        new.spec_name,
+        new.spec_version
+    );
+    tokio::runtime::Builder
+        ::new_current_thread()
+        .enable_all()
+        .build()?
+        .block_on(submit_upgrade(port, code, authorize, &signer, config.node.block_production))?;
+
+    let new_spec_version = spec_version(port)?;
+    println!("🐋 Runtime upgraded, spec_version {old_spec_version} -> {new_spec_version}");
+    Ok(())
+}
+
+fn signer(uri: &str) -> Result<Keypair> {
+    let invalid = |err: &dyn std::fmt::Display| {
+        CarpError::Config(format!("upgrade.signer is not a valid secret URI: {err}"))
+    };
+    let uri: SecretUri = uri.parse().map_err(|err| invalid(&err))?;
+    Keypair::from_uri(&uri).map_err(|err| invalid(&err))
+}
+
+async fn submit_upgrade(
+    port: u16,
+    code: Vec<u8>,
+    authorize: bool,
+    signer: &Keypair,
+    block_production: BlockProduction
+) -> Result<()> {
+    let api = Client::from_insecure_url(format!("ws://127.0.0.1:{port}")).await.map_err(failed)?;
+    let at_block = api.at_current_block().await.map_err(failed)?;
+    if at_block.metadata_ref().pallet_by_name("Sudo").is_none() {
+        return Err(failed("the running runtime has no sudo, upgrades are dispatched through Sudo"));
+    }
+    let storage_key = account::decode_hex(SUDO_KEY_STORAGE).expect("valid storage key");
+    let sudo_key = at_block.storage().fetch_raw(storage_key).await.map_err(|err| {
+        failed(format!("the chain has no sudo key: {err}"))
+    })?;
+    let signer_account = signer.public_key().0;
+    if sudo_key != signer_account {
+        let sudo_key = <[u8; 32]>::try_from(sudo_key.as_slice())
+            .map(|key| account::ss58_encode(&key))
+            .unwrap_or_else(|_| format!("0x{}", account::encode_hex(&sudo_key)));
+        return Err(
+            failed(
+                format!(
+                    "upgrade.signer is {} but the sudo key is {}",
+                    account::ss58_encode(&signer_account),
+                    sudo_key
+                )
+            )
+        );
+    }
+    // Instant mode only seals blocks with transactions and manual mode none at all, but applying
+    // the new code takes a few more blocks after the upgrade is included
+    let _sealer = match block_production {
+        BlockProduction::Interval => None,
+        BlockProduction::Instant | BlockProduction::Manual => {
+            println!(
+                "Authoring a block every {}ms until the upgrade is applied",
+                UPGRADE_BLOCK_TIME.as_millis()
+            );
+            Some(Sealer::every(port, UPGRADE_BLOCK_TIME)?)
+        }
+    };
+    // Subscribed before submitting, a solo chain updates its code in the including block
+    let mut blocks = api.stream_best_blocks().await.map_err(failed)?;
+
+    if authorize {
+        let code_hash = Blake2b256::digest(&code);
+        let call = Value::named_variant("authorize_upgrade", [
+            ("code_hash", Value::from_bytes(code_hash)),
+        ]);
+        sudo(&api, signer, call).await?;
+        println!("Upgrade authorized, applying it");
+
+        let apply = dynamic::transaction("System", "apply_authorized_upgrade", (
+            Value::from_bytes(&code),
+        ));
+        let tx = api.tx().await.map_err(failed)?.create_unsigned(&apply).map_err(failed)?;
+        wait_in_block(tx).await?;
+    } else {
+        let call = Value::named_variant("set_code", [("code", Value::from_bytes(&code))]);
+        sudo(&api, signer, call).await?;
+    }
+
+    println!("Waiting for System.CodeUpdated...");
+    let code_updated = async {
+        while let Some(block) = blocks.next().await {
+            let block = block.map_err(failed)?;
+            let at_block = block.at().await.map_err(failed)?;
+            let events = at_block.events().fetch().await.map_err(failed)?;
+            for event in events.iter() {
+                let event = event.map_err(failed)?;
+                if event.pallet_name() == "System" && event.event_name() == "CodeUpdated" {
+                    println!("Code updated in block #{}", block.number());
+                    return Ok(());
+                }
+            }
+        }
+        Err(failed("block subscription ended"))
+    };
+    tokio::time
+        ::timeout(CODE_UPDATED_TIMEOUT, code_updated).await
+        .map_err(|_| {
+            failed(format!("no System.CodeUpdated event after {}s", CODE_UPDATED_TIMEOUT.as_secs()))
+        })?
+}
+
+/// Dispatches a `System` call as root, without weight checks since code upgrades fill a block
+async fn sudo(api: &Client, signer: &Keypair, call: Value) -> Result<()> {
+    let tx = dynamic::transaction("Sudo", "sudo_unchecked_weight", (
+        Value::unnamed_variant("System", [call]),
+        Value::named_composite([
+            ("ref_time", Value::u128(0)),
+            ("proof_size", Value::u128(0)),
+        ]),
+    ));
+    let tx = api
+        .tx().await
+        .map_err(failed)?
+        .create_signed(&tx, signer, Default::default()).await
+        .map_err(failed)?;
+    let events = wait_in_block(tx).await?;
+
+    // Sudo succeeds even when the inner call fails, the result is in `Sudid`
+    for event in events.iter() {
+        let event = event.map_err(failed)?;
+        if event.pallet_name() != "Sudo" || event.event_name() != "Sudid" {
+            continue;
+        }
+        let fields: Value = event.decode_fields_unchecked_as().map_err(failed)?;
+        if
+            let Some(result) = fields.at("sudo_result") &&
+            let ValueDef::Variant(variant) = &result.value &&
+            variant.name == "Err"
+        {
+            return Err(failed(format!("sudo call failed: {result}")));
+        }
+    }
+    Ok(())
+}
+
+async fn wait_in_block(tx: Transaction) -> Result<ExtrinsicEvents<PolkadotConfig>> {
+    let in_block = async {
+        let mut progress = tx.submit_and_watch().await.map_err(failed)?;
+        while let Some(status) = progress.next().await {
+            match status.map_err(failed)? {
+                | TransactionStatus::InBestBlock(in_block)
+                | TransactionStatus::InFinalizedBlock(in_block) => {
+                    return in_block.wait_for_success().await.map_err(failed);
+                }
+                TransactionStatus::Error { message } |
+                TransactionStatus::Invalid { message } |
+                TransactionStatus::Dropped { message } => {
+                    return Err(failed(message));
+                }
+                _ => {}
+            }
+        }
+        Err(failed("transaction status subscription ended"))
+    };
+    tokio::time
+        ::timeout(INCLUSION_TIMEOUT, in_block).await
+        .map_err(|_| {
+            failed(
+                format!(
+                    "transaction not included after {}s, is the node authoring blocks?",
+                    INCLUSION_TIMEOUT.as_secs()
+                )
+            )
+        })?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derives_signer_from_secret_uri() {
+        let alice = signer("//Alice").unwrap();
+        assert_eq!(
+            account::ss58_encode(&alice.public_key().0),
+            "5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY"
+        );
+        assert!(matches!(signer("not a uri//"), Err(CarpError::Config(_))));
+    }
+}
@@ -0,0 +1,183 @@
+use std::{ fs, path::Path, process::{ Command, Stdio } };
+
+use serde_json::Value;
+
+use crate::dependency::{ Dependency, GitInstallType };
+
+/// Version reported by `<bin> --version`, e.g. `polkadot-omni-node 0.1.0-d1d92ab7600`
+pub struct InstalledVersion {
+    pub version: String,
+    pub commit: Option<String>,
+}
+
+pub enum VersionCheck {
+    Match,
+    Mismatch {
+        found: String,
+        expected: String,
+    },
+    /// The pin can't be compared with what the binary reports
+    Unknown(String),
+}
+
+/// Runs `<bin> --version`. Returns `Ok(None)` when the binary is not installed.
+pub fn probe(bin: &Path) -> Result<Option<InstalledVersion>, std::io::Error> {
+    let output = match Command::new(bin).arg("--version").stdin(Stdio::null()).output() {
+        Ok(output) => output,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(None);
+        }
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    Ok(Some(parse_version(&String::from_utf8_lossy(&output.stdout))))
+}
+
+fn parse_version(output: &str) -> InstalledVersion {
+    let token = output
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().last())
+        .unwrap_or_default();
+    match token.split_once('-') {
+        Some((version, commit)) if is_commit(commit) => {
+            InstalledVersion { version: version.to_string(), commit: Some(commit.to_string()) }
+        }
+        _ => InstalledVersion { version: token.to_string(), commit: None },
+    }
+}
+
+fn is_commit(s: &str) -> bool {
+    s.len() >= 7 && s.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Either hash may be abbreviated
+fn same_commit(a: &str, b: &str) -> bool {
+    let len = a.len().min(b.len());
+    len >= 7 && a[..len].eq_ignore_ascii_case(&b[..len])
+}
+
+pub fn check(dep: &Dependency, installed: &InstalledVersion) -> VersionCheck {
+    if let Some(expected) = &dep.expected_version {
+        return if &installed.version == expected {
+            VersionCheck::Match
+        } else {
+            VersionCheck::Mismatch { found: installed.version.clone(), expected: expected.clone() }
+        };
+    }
+
+    let Some(git) = dep.git() else {
+        return VersionCheck::Match;
+    };
+    let Some(commit) = &installed.commit else {
+        return VersionCheck::Unknown(format!("{} does not report a commit", dep.bin));
+    };
+
+    let expected = match git.install_type {
+        GitInstallType::CommitHash => git.tag_or_hash.clone(),
+        GitInstallType::Tag => {
+            match installed_tag_commit(&dep.root, &dep.install_bin, &git.tag_or_hash) {
+                Some(expected) => expected,
+                None => {
+                    return VersionCheck::Unknown(
+                        format!(
+                            "no cargo install record of {} at {}",
+                            dep.install_bin,
+                            git.tag_or_hash
+                        )
+                    );
+                }
+            }
+        }
+    };
+
+    if same_commit(commit, &expected) {
+        VersionCheck::Match
+    } else {
+        VersionCheck::Mismatch {
+            found: format!("{}-{}", installed.version, commit),
+            expected: match git.install_type {
+                GitInstallType::CommitHash => expected,
+                GitInstallType::Tag => format!("{} ({})", git.tag_or_hash, expected),
+            },
+        }
+    }
+}
+
+/// Tags aren't visible in `--version`, so look up the commit cargo resolved the tag to when it
+/// installed the package into `root`
+fn installed_tag_commit(root: &Path, package: &str, tag: &str) -> Option<String> {
+    let installs: Value = serde_json
+        ::from_str(&fs::read_to_string(root.join(".crates2.json")).ok()?)
+        .ok()?;
+    let tag_query = format!("tag={tag}#");
+
+    installs["installs"]
+        .as_object()?
+        .keys()
+        .filter(|key| key.split_whitespace().next() == Some(package))
+        .find_map(|key| {
+            let (_, commit) = key.split_once(&tag_query)?;
+            Some(commit.trim_end_matches(')').to_string())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn parses_version_with_commit() {
+        let installed = parse_version("polkadot-omni-node 0.1.0-d1d92ab7600\n");
+        assert_eq!(installed.version, "0.1.0");
+        assert_eq!(installed.commit.as_deref(), Some("d1d92ab7600"));
+    }
+
+    #[test]
+    fn parses_version_without_commit() {
+        let installed = parse_version("chain-spec-builder 10.0.0\n");
+        assert_eq!(installed.version, "10.0.0");
+        assert_eq!(installed.commit, None);
+
+        // A pre-release suffix is not a commit
+        let installed = parse_version("eth-rpc 0.1.0-dev");
+        assert_eq!(installed.version, "0.1.0-dev");
+        assert_eq!(installed.commit, None);
+
+        assert_eq!(parse_version("").version, "");
+    }
+
+    #[test]
+    fn compares_abbreviated_commits() {
+        assert!(same_commit("d1d92ab7600", "d1d92ab76004ce349a97fc5d325eaf9a4a7101b7"));
+        assert!(same_commit("D1D92AB", "d1d92ab76004ce349a97fc5d325eaf9a4a7101b7"));
+        assert!(!same_commit("d1d92ab7601", "d1d92ab76004ce349a97fc5d325eaf9a4a7101b7"));
+        // Too short to identify a commit
+        assert!(!same_commit("d1d92a", "d1d92ab76004ce349a97fc5d325eaf9a4a7101b7"));
+    }
+
+    #[test]
+    fn reads_tag_commit_from_cargo_install_record() {
+        let root = std::env::temp_dir().join(format!("carp-crates2-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let source = "git+https://github.com/paritytech/polkadot-sdk.git?tag=polkadot-stable2412";
+        let mut keys = serde_json::Map::new();
+        let builder = format!("staging-chain-spec-builder 10.0.0 ({source}#0123456789abcdef)");
+        keys.insert(builder, json!({}));
+        keys.insert(format!("polkadot-omni-node 0.1.0 ({source}#8c8e5a3d4ab1234)"), json!({}));
+        let installs = json!({ "installs": keys });
+        fs::write(root.join(".crates2.json"), installs.to_string()).unwrap();
+
+        let commit = installed_tag_commit(&root, "polkadot-omni-node", "polkadot-stable2412");
+        let other_tag = installed_tag_commit(&root, "polkadot-omni-node", "polkadot-stable2409");
+        let other_package = installed_tag_commit(&root, "eth-rpc", "polkadot-stable2412");
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(commit.as_deref(), Some("8c8e5a3d4ab1234"));
+        assert_eq!(other_tag, None);
+        assert_eq!(other_package, None);
+    }
+}